@@ -0,0 +1,47 @@
+use chipper::cpu::Cpu;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const TICKS: u64 = 10_000;
+
+// Arithmetic, drawing and BCD stores in a tight loop.
+const ROM: &[u8] = &[
+    0x60, 0x00, // LD V0, 0x00
+    0x61, 0x00, // LD V1, 0x00
+    0xA3, 0x00, // LD I, 0x300
+    0x70, 0x01, // ADD V0, 0x01
+    0x80, 0x14, // ADD V0, V1
+    0x81, 0x03, // XOR V1, V0
+    0xD0, 0x15, // DRW V0, V1, 5
+    0xF0, 0x33, // LD B, V0
+    0x30, 0xFF, // SE V0, 0xFF
+    0x12, 0x06, // JP 0x206
+    0x12, 0x00, // JP 0x200
+];
+
+fn setup(cached: bool) -> Cpu {
+    let mut cpu = Cpu::default();
+    cpu.set_decode_cache(cached);
+    cpu.load_rom(ROM);
+    cpu
+}
+
+fn run(mut cpu: Cpu) -> Cpu {
+    for _ in 0..TICKS {
+        cpu.tick();
+    }
+    cpu
+}
+
+fn executor(c: &mut Criterion) {
+    let mut group = c.benchmark_group("executor");
+    group.throughput(Throughput::Elements(TICKS));
+    for &(name, cached) in [("cached", true), ("uncached", false)].iter() {
+        group.bench_function(name, |b| {
+            b.iter_batched(|| setup(cached), run, BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, executor);
+criterion_main!(benches);
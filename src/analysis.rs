@@ -0,0 +1,406 @@
+//! Finds out what a ROM needs without running it.
+//!
+//! [`analyze`] follows every path from `0x200`: jumps, calls, returns and both
+//! sides of skips. `Bnnn` jumps depend on V0, so their targets are only
+//! listed. Along each path it follows the address in I as far as `Annn` sets
+//! it, so bytes drawn by `Dxyn` are known to be sprites and bytes read or
+//! written by `Fx33`, `Fx55` and `Fx65` to be data. Code reached with
+//! different values of I is walked once for each. Bytes nothing reaches are
+//! left unclassified; they are usually data reached through `Fx1E`.
+
+use crate::instruction::{Extension, Instruction};
+use crate::memory::PROGRAM_START;
+use crate::variant::Variant;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    Unreached,
+    Code,
+    Sprite,
+    Data,
+}
+
+/// Behaviour the ROM relies on that differs between interpreters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuirkHint {
+    /// `8xy6` or `8xyE` with two registers: the VIP shifted Vy into Vx, later
+    /// interpreters shift Vx in place.
+    ShiftSource,
+    /// `Fx55` or `Fx65`: the VIP left I past the registers, SCHIP left it
+    /// alone.
+    LoadStoreIncrement,
+    /// `Bnnn`: SCHIP jumps to `xnn` plus Vx instead of `nnn` plus V0.
+    JumpRegister,
+    /// `0nnn`: a call into VIP machine code, which no other interpreter runs.
+    MachineCode,
+}
+
+/// What [`analyze`] found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Analysis {
+    // One entry per ROM byte.
+    kinds: Vec<ByteKind>,
+    /// Reachable instructions counted by their `pattern`.
+    pub opcodes: BTreeMap<&'static str, usize>,
+    pub extensions: BTreeSet<Extension>,
+    /// Addresses of reachable `Bnnn` jumps.
+    pub indirect_jumps: Vec<usize>,
+    /// Addresses of reachable words that do not decode.
+    pub unknown: Vec<usize>,
+    /// Jumps, calls and skips from inside the ROM to outside it, as
+    /// `(from, to)`.
+    pub outside: Vec<(usize, usize)>,
+    pub hints: BTreeSet<QuirkHint>,
+}
+
+/// Where control goes after an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Flow {
+    Next,
+    Jump(usize),
+    /// To the subroutine, then back to the next instruction.
+    Call(usize),
+    Return,
+    /// To the next instruction or the one after it.
+    Skip,
+    /// To an address computed at run time.
+    Indirect,
+    /// Nowhere: the program exits or the word is not an instruction.
+    Halt,
+}
+
+impl Flow {
+    pub(crate) fn of(instruction: Instruction) -> Self {
+        use Instruction::*;
+        match instruction {
+            Jp(nnn) => Flow::Jump(nnn),
+            Call(nnn) => Flow::Call(nnn),
+            Ret => Flow::Return,
+            SeByte(..) | SneByte(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) => Flow::Skip,
+            JpV0(_) => Flow::Indirect,
+            Exit | Unknown(_) => Flow::Halt,
+            _ => Flow::Next,
+        }
+    }
+}
+
+/// Decodes the instruction at `address` in a ROM loaded at `0x200`, if it
+/// lies wholly inside the ROM.
+pub(crate) fn instruction_at(rom: &[u8], address: usize) -> Option<Instruction> {
+    let offset = address.checked_sub(PROGRAM_START)?;
+    let word = rom.get(offset..offset + 2)?;
+    let instruction = Instruction::decode(u16::from_be_bytes([word[0], word[1]]));
+    if offset + instruction.size() > rom.len() {
+        return None;
+    }
+    Some(instruction)
+}
+
+/// Where execution can continue after the instruction at `address`, with the
+/// subroutine of a call first.
+pub(crate) fn successors(rom: &[u8], address: usize, instruction: Instruction) -> Vec<usize> {
+    let next = address + instruction.size();
+    match Flow::of(instruction) {
+        Flow::Next => vec![next],
+        Flow::Jump(target) => vec![target],
+        Flow::Call(target) => vec![target, next],
+        Flow::Skip => {
+            let size = instruction_at(rom, next).map_or(2, Instruction::size);
+            vec![next, next + size]
+        }
+        Flow::Return | Flow::Indirect | Flow::Halt => Vec::new(),
+    }
+}
+
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        kinds: vec![ByteKind::Unreached; rom.len()],
+        opcodes: BTreeMap::new(),
+        extensions: BTreeSet::new(),
+        indirect_jumps: Vec::new(),
+        unknown: Vec::new(),
+        outside: Vec::new(),
+        hints: BTreeSet::new(),
+    };
+    // Sprites and data are marked once all code is known, since code wins.
+    let mut sprites = Vec::new();
+    let mut data = Vec::new();
+
+    // Addresses are walked again for every I they are reached with, so each
+    // path marks what it draws, but only counted the first time.
+    let mut visited = BTreeSet::new();
+    let mut counted = BTreeSet::new();
+    // Addresses still to walk, with I on arrival if it is known.
+    let mut pending = vec![(PROGRAM_START, None)];
+    while let Some((address, mut i)) = pending.pop() {
+        if !visited.insert((address, i)) {
+            continue;
+        }
+        let instruction = match instruction_at(rom, address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let first = counted.insert(address);
+        if let Instruction::Unknown(_) = instruction {
+            if first {
+                analysis.unknown.push(address);
+            }
+            continue;
+        }
+        if first {
+            analysis.count(address, instruction);
+        }
+
+        i = match (instruction, i) {
+            (Instruction::LdI(nnn), _) => Some(nnn),
+            (Instruction::LdILong, _) => {
+                let offset = address - PROGRAM_START;
+                Some(u16::from_be_bytes([rom[offset + 2], rom[offset + 3]]) as usize)
+            }
+            (Instruction::Drw(_, _, n), Some(i)) => {
+                sprites.push(i..i + n);
+                Some(i)
+            }
+            (Instruction::DrwHigh(..), Some(i)) => {
+                sprites.push(i..i + 32);
+                Some(i)
+            }
+            (Instruction::LdB(_), Some(i)) => {
+                data.push(i..i + 3);
+                Some(i)
+            }
+            (Instruction::SaveRange(x, y), Some(i)) | (Instruction::LoadRange(x, y), Some(i)) => {
+                data.push(i..i + x.abs_diff(y) + 1);
+                Some(i)
+            }
+            // Whether I moves afterwards depends on the interpreter.
+            (Instruction::LdIVx(x), Some(i)) | (Instruction::LdVxI(x), Some(i)) => {
+                data.push(i..i + x + 1);
+                None
+            }
+            (Instruction::AddI(_), _) | (Instruction::LdF(_), _) | (Instruction::LdHf(_), _) => {
+                None
+            }
+            (_, i) => i,
+        };
+
+        for target in successors(rom, address, instruction) {
+            if instruction_at(rom, target).is_none() {
+                if first {
+                    analysis.outside.push((address, target));
+                }
+            } else {
+                pending.push((target, i));
+            }
+        }
+    }
+
+    for range in sprites {
+        analysis.mark(range, ByteKind::Sprite);
+    }
+    for range in data {
+        analysis.mark(range, ByteKind::Data);
+    }
+    analysis.indirect_jumps.sort_unstable();
+    analysis.unknown.sort_unstable();
+    analysis.outside.sort_unstable();
+    analysis
+}
+
+impl Analysis {
+    /// What the byte at `address` was found to be. Addresses outside the ROM
+    /// are unreached.
+    pub fn kind(&self, address: usize) -> ByteKind {
+        address
+            .checked_sub(PROGRAM_START)
+            .and_then(|offset| self.kinds.get(offset))
+            .copied()
+            .unwrap_or(ByteKind::Unreached)
+    }
+
+    /// The ROM split into runs of bytes of the same kind, by address.
+    pub fn regions(&self) -> Vec<(Range<usize>, ByteKind)> {
+        let mut regions: Vec<(Range<usize>, ByteKind)> = Vec::new();
+        for (offset, &kind) in self.kinds.iter().enumerate() {
+            let address = PROGRAM_START + offset;
+            match regions.last_mut() {
+                Some((range, last)) if *last == kind => range.end = address + 1,
+                _ => regions.push((address..address + 1, kind)),
+            }
+        }
+        regions
+    }
+
+    /// The platform the ROM most likely needs: the newest whose instructions
+    /// it uses, or the VIP if it calls machine code.
+    pub fn variant(&self) -> Variant {
+        if self.extensions.contains(&Extension::XoChip) {
+            Variant::XoChip
+        } else if self.extensions.contains(&Extension::SuperChip) {
+            Variant::SuperChip
+        } else if self.hints.contains(&QuirkHint::MachineCode) {
+            Variant::OriginalChip8
+        } else {
+            Variant::ModernChip8
+        }
+    }
+
+    // Notes a reachable instruction in the counts, extensions and hints.
+    fn count(&mut self, address: usize, instruction: Instruction) {
+        self.mark(address..address + instruction.size(), ByteKind::Code);
+        *self.opcodes.entry(instruction.pattern()).or_insert(0) += 1;
+        self.extensions.extend(instruction.extension());
+        match instruction {
+            Instruction::Shr(x, y) | Instruction::Shl(x, y) if x != y => {
+                self.hints.insert(QuirkHint::ShiftSource);
+            }
+            Instruction::LdIVx(_) | Instruction::LdVxI(_) => {
+                self.hints.insert(QuirkHint::LoadStoreIncrement);
+            }
+            Instruction::JpV0(_) => {
+                self.hints.insert(QuirkHint::JumpRegister);
+                self.indirect_jumps.push(address);
+            }
+            Instruction::Sys(_) => {
+                self.hints.insert(QuirkHint::MachineCode);
+            }
+            _ => {}
+        }
+    }
+
+    // Marks the part of `range` inside the ROM, leaving code as it is.
+    fn mark(&mut self, range: Range<usize>, kind: ByteKind) {
+        let start = range
+            .start
+            .saturating_sub(PROGRAM_START)
+            .min(self.kinds.len());
+        let end = range
+            .end
+            .saturating_sub(PROGRAM_START)
+            .min(self.kinds.len());
+        for byte in self.kinds[start..end].iter_mut() {
+            if *byte != ByteKind::Code {
+                *byte = kind;
+            }
+        }
+    }
+}
+
+impl fmt::Display for ByteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ByteKind::Unreached => "unreached",
+            ByteKind::Code => "code",
+            ByteKind::Sprite => "sprite",
+            ByteKind::Data => "data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for QuirkHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hint = match self {
+            QuirkHint::ShiftSource => "shifts with 8xy6/8xyE, which differ on the VIP",
+            QuirkHint::LoadStoreIncrement => "Fx55/Fx65 leave I in different places",
+            QuirkHint::JumpRegister => "Bnnn adds Vx instead of V0 on SCHIP",
+            QuirkHint::MachineCode => "0nnn calls VIP machine code",
+        };
+        write!(f, "{}", hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn walks_calls_skips_and_separates_sprites() {
+        let rom = [
+            0x22, 0x08, // 200: CALL 208
+            0x30, 0x01, // 202: SE V0, 1
+            0x12, 0x02, // 204: JP 202
+            0x00, 0xFD, // 206: EXIT
+            0xA2, 0x0E, // 208: LD I, 20E
+            0xD0, 0x12, // 20A: DRW V0, V1, 2
+            0x00, 0xEE, // 20C: RET
+            0xF0, 0x90, // 20E: sprite
+            0xFF, 0xFF, // 210: never reached
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(
+            vec![
+                (0x200..0x20E, ByteKind::Code),
+                (0x20E..0x210, ByteKind::Sprite),
+                (0x210..0x212, ByteKind::Unreached),
+            ],
+            analysis.regions()
+        );
+        assert_eq!(Some(&1), analysis.opcodes.get("2nnn"));
+        assert_eq!(Some(&1), analysis.opcodes.get("Dxyn"));
+        assert_eq!(None, analysis.opcodes.get("????"));
+        assert!(analysis.unknown.is_empty());
+        assert_eq!(Variant::SuperChip, analysis.variant());
+    }
+
+    #[test]
+    fn flags_indirect_jumps_and_quirk_hints() {
+        let rom = [
+            0xA3, 0x00, // 200: LD I, 300
+            0xF2, 0x55, // 202: LD [I], V2
+            0x81, 0x26, // 204: SHR V1, V2
+            0xB2, 0x10, // 206: JP V0, 210
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(vec![0x206], analysis.indirect_jumps);
+        assert_eq!(
+            vec![
+                QuirkHint::ShiftSource,
+                QuirkHint::LoadStoreIncrement,
+                QuirkHint::JumpRegister,
+            ],
+            analysis.hints.iter().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(Variant::ModernChip8, analysis.variant());
+        assert_eq!(ByteKind::Unreached, analysis.kind(0x300));
+    }
+
+    #[test]
+    fn guesses_xo_chip_and_follows_long_loads() {
+        let rom = [
+            0xF0, 0x00, 0x02, 0x08, // 200: LD I, LONG 208
+            0xD0, 0x11, // 204: DRW V0, V1, 1
+            0x12, 0x0A, // 206: JP 20A, past the end
+            0x3C, // 208: sprite
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(ByteKind::Code, analysis.kind(0x202));
+        assert_eq!(ByteKind::Sprite, analysis.kind(0x208));
+        assert_eq!(vec![(0x206, 0x20A)], analysis.outside);
+        assert_eq!(Variant::XoChip, analysis.variant());
+    }
+
+    #[test]
+    fn follows_each_value_of_i() {
+        let rom = [
+            0xA2, 0x0E, // 200: LD I, 20E
+            0x22, 0x0A, // 202: CALL 20A
+            0xA2, 0x0F, // 204: LD I, 20F
+            0x22, 0x0A, // 206: CALL 20A
+            0x00, 0xFD, // 208: EXIT
+            0xD0, 0x01, // 20A: DRW V0, V0, 1
+            0x00, 0xEE, // 20C: RET
+            0x80, // 20E: sprite
+            0x40, // 20F: sprite
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(ByteKind::Sprite, analysis.kind(0x20E));
+        assert_eq!(ByteKind::Sprite, analysis.kind(0x20F));
+        assert_eq!(Some(&1), analysis.opcodes.get("Dxyn"));
+        assert_eq!(Some(&2), analysis.opcodes.get("2nnn"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Reports what a ROM needs without running it.
+//!
+//! ```text
+//! chipper-analyze [--dot <file>] [--symbols <file>] <rom>
+//! ```
+//!
+//! Prints the guessed platform, behaviour that differs between interpreters,
+//! the extensions and opcodes used, how the ROM splits into code, sprites and
+//! data, and anything the walk could not follow. See `src/analysis.rs` for how the ROM is walked. `--dot` also
+//! writes the control-flow graph in Graphviz DOT, or to stdout for `-`.
+//! `--symbols` names addresses in the output and the graph with the labels
+//! of a symbol file (see `src/symbols.rs`).
+
+use chipper::analysis::analyze;
+use chipper::control_flow::FlowGraph;
+use chipper::instruction::Instruction;
+use chipper::memory::PROGRAM_START;
+use chipper::symbols::SymbolMap;
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: chipper-analyze [--dot <file>] [--symbols <file>] <rom>";
+
+fn main() {
+    let mut dot = None;
+    let mut symbols = SymbolMap::new();
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => match args.next() {
+                Some(file) => dot = Some(file),
+                None => fail_usage("--dot needs a file"),
+            },
+            "--symbols" => match args.next() {
+                Some(file) => {
+                    symbols = SymbolMap::load(&file).unwrap_or_else(|e| {
+                        eprintln!("{}: {}", file, e);
+                        process::exit(2);
+                    })
+                }
+                None => fail_usage("--symbols needs a file"),
+            },
+            _ if arg.starts_with("--") => fail_usage(&format!("unknown option: {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let path = match &paths[..] {
+        [path] => path,
+        _ => fail_usage("expected one ROM"),
+    };
+    let rom = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        process::exit(2);
+    });
+
+    if let Some(file) = dot {
+        let graph = FlowGraph::build(&rom).to_labelled_dot(&symbols);
+        if file == "-" {
+            print!("{}", graph);
+            return;
+        }
+        if let Err(e) = fs::write(&file, graph) {
+            eprintln!("could not write {}: {}", file, e);
+            process::exit(2);
+        }
+    }
+
+    let analysis = analyze(&rom);
+    println!("platform: {:?}", analysis.variant());
+    let extensions: Vec<String> = analysis.extensions.iter().map(|e| e.to_string()).collect();
+    if !extensions.is_empty() {
+        println!("extensions: {}", extensions.join(", "));
+    }
+    for hint in analysis.hints.iter() {
+        println!("note: {}", hint);
+    }
+
+    println!();
+    println!("regions:");
+    for (range, kind) in analysis.regions() {
+        let label = match symbols.label_at(range.start) {
+            Some(label) => format!("  ({})", label),
+            None => String::new(),
+        };
+        println!(
+            "  {:03X}-{:03X}  {:>4} bytes  {}{}",
+            range.start,
+            range.end - 1,
+            range.len(),
+            kind,
+            label
+        );
+    }
+
+    println!();
+    println!("opcodes:");
+    for (pattern, count) in analysis.opcodes.iter() {
+        println!("  {}  {}", pattern, count);
+    }
+
+    for &address in analysis.indirect_jumps.iter() {
+        let instruction = Instruction::decode(word_at(&rom, address));
+        println!(
+            "indirect jump at {}: {}",
+            symbols.locate(address),
+            symbols.labelled(instruction)
+        );
+    }
+    for &address in analysis.unknown.iter() {
+        println!("unknown opcode at {}", symbols.locate(address));
+    }
+    for &(from, to) in analysis.outside.iter() {
+        println!(
+            "{} goes to {}, outside the ROM",
+            symbols.locate(from),
+            symbols.locate(to)
+        );
+    }
+}
+
+fn word_at(rom: &[u8], address: usize) -> u16 {
+    let offset = address - PROGRAM_START;
+    u16::from_be_bytes([rom[offset], rom[offset + 1]])
+}
+
+fn fail_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,424 @@
+//! Interactive command line debugger.
+//!
+//! ```text
+//! chipper-debug [--ipf <n>] [--symbols <file>] [--database <programs.json> | --no-database]
+//!               [--config-dir <dir>] <rom>
+//! ```
+//!
+//! The ROM runs with the same settings as in `chipper-term`: its database
+//! entry, then its config file, then `--ipf`; see `src/config.rs`.
+//! Type `help` at the prompt for the list of commands. With a symbol file
+//! (see `src/symbols.rs`) addresses are shown as labels and labels can be
+//! typed wherever an address is expected.
+
+use chipper::config::RomConfig;
+use chipper::cpu::Cpu;
+use chipper::database::Database;
+use chipper::debugger::{Debugger, StopReason};
+use chipper::instruction::Instruction;
+use chipper::keypad::{ChipKey, Keypad};
+use chipper::memory::{Protection, RegionKind, CHIP8_RAM, PROGRAM_START};
+use chipper::runtime::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use chipper::screenshot::Screenshot;
+use chipper::symbols::SymbolMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: chipper-debug [--ipf <n>] [--symbols <file>] \
+                     [--database <programs.json> | --no-database] [--config-dir <dir>] <rom>";
+const HELP: &str = "\
+commands:
+  s [n]        step n instructions (default 1)
+  b [n]        step back n instructions (default 1)
+  f [n]        run n frames, stopping at breakpoints (default 1)
+  r [n]        rewind n frames (default 1)
+  c            continue until a breakpoint, pausing after a minute of
+               frames
+  l [addr] [n] list n instructions from addr (default the PC and 10)
+  bp <addr>    set a breakpoint
+  del <addr>   delete a breakpoint
+  bps          list breakpoints
+  key <k|->    hold hex key k, or release all keys with -
+  regs         show registers
+  bt           show the call stack
+  shot <file> [scale]
+               save the screen as a .pbm or .png image
+  smc [on|off] list writes over executed code, or turn stopping on them
+               on or off
+  protect <region> <rw|log|fault>
+               set how writes to interpreter, font, program, stack or
+               display memory are handled
+  q            quit
+addresses are hex, or labels from the symbol file such as loop or loop+4";
+const DEFAULT_SCREENSHOT_SCALE: usize = 8;
+const DEFAULT_LISTING: usize = 10;
+// Upper bound for `c` so a ROM that never hits a breakpoint returns control.
+const CONTINUE_FRAMES: usize = 60 * 60;
+
+fn main() {
+    let mut user = RomConfig::default();
+    let mut symbols = SymbolMap::new();
+    let mut database_file = None;
+    let mut no_database = false;
+    let mut config_dir = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ipf" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => user.instructions_per_frame = Some(n),
+                None => fail_usage("--ipf needs a number"),
+            },
+            "--database" => match args.next() {
+                Some(file) => database_file = Some(file),
+                None => fail_usage("--database needs a file"),
+            },
+            "--no-database" => no_database = true,
+            "--config-dir" => match args.next() {
+                Some(dir) => config_dir = Some(dir),
+                None => fail_usage("--config-dir needs a directory"),
+            },
+            "--symbols" => match args.next() {
+                Some(file) => {
+                    symbols = SymbolMap::load(&file).unwrap_or_else(|e| {
+                        eprintln!("{}: {}", file, e);
+                        process::exit(1);
+                    })
+                }
+                None => fail_usage("--symbols needs a file"),
+            },
+            _ if arg.starts_with("--") => fail_usage(&format!("unknown option: {}", arg)),
+            _ => path = Some(arg),
+        }
+    }
+    if database_file.is_some() && no_database {
+        fail_usage("--database and --no-database cannot be combined");
+    }
+    let path = path.unwrap_or_else(|| fail_usage("no ROM given"));
+    let rom = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        process::exit(1);
+    });
+    if rom.len() > CHIP8_RAM - PROGRAM_START {
+        eprintln!("{} is too large to fit in memory", path);
+        process::exit(1);
+    }
+
+    let database = if no_database {
+        Database::new()
+    } else {
+        Database::builtin_with(database_file.as_ref()).unwrap_or_else(|e| {
+            eprintln!(
+                "could not load {}: {}",
+                database_file.unwrap_or_default(),
+                e
+            );
+            process::exit(1);
+        })
+    };
+    let config_dir = config_dir.as_deref().map(Path::new);
+    let config = RomConfig::for_rom(Path::new(&path), &rom, &database, config_dir, user)
+        .unwrap_or_else(|e| {
+            eprintln!("could not load {}", e);
+            process::exit(1);
+        });
+    let ipf = config
+        .instructions_per_frame
+        .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME);
+
+    let mut cpu = config.cpu_builder().build();
+    cpu.load_rom(&rom);
+    let mut dbg = Debugger::new(cpu, ipf);
+    print_location(&dbg, &symbols);
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let arg = words.next();
+        let result = execute(&mut dbg, &symbols, command, arg, words);
+        for (pc, violation) in dbg.take_violations() {
+            println!("warning: {}: {}", symbols.describe(pc), violation);
+        }
+        if let Err(message) = result {
+            if message.is_empty() {
+                break;
+            }
+            println!("{}", message);
+        }
+    }
+}
+
+/// Runs one command. An empty error message means quit.
+fn execute<'a>(
+    dbg: &mut Debugger,
+    symbols: &SymbolMap,
+    command: &str,
+    arg: Option<&str>,
+    mut words: impl Iterator<Item = &'a str>,
+) -> Result<(), String> {
+    match command {
+        "s" => {
+            for _ in 0..parse_count(arg)? {
+                dbg.step();
+            }
+        }
+        "b" => {
+            for _ in 0..parse_count(arg)? {
+                if !dbg.step_back() {
+                    println!("reached the start of the history");
+                    break;
+                }
+            }
+        }
+        "f" => {
+            let reason = dbg.run_frames(parse_count(arg)?);
+            report(dbg, reason, symbols);
+        }
+        "r" => {
+            if !dbg.rewind_frames(parse_count(arg)?) {
+                println!("reached the start of the history");
+            }
+        }
+        "c" => {
+            let reason = dbg.run_frames(CONTINUE_FRAMES);
+            if reason == StopReason::Finished {
+                println!(
+                    "paused after {} frames without stopping (c to keep going)",
+                    CONTINUE_FRAMES
+                );
+            }
+            report(dbg, reason, symbols);
+        }
+        "l" => {
+            let start = match arg {
+                Some(_) => parse_address(arg, symbols)?,
+                None => dbg.cpu().pc(),
+            };
+            let count = match words.next() {
+                Some(n) => parse_count(Some(n))?,
+                None => DEFAULT_LISTING,
+            };
+            print_listing(dbg.cpu(), symbols, start, count);
+            return Ok(());
+        }
+        "bp" => {
+            dbg.add_breakpoint(parse_address(arg, symbols)?);
+            return Ok(());
+        }
+        "del" => {
+            let address = parse_address(arg, symbols)?;
+            if !dbg.remove_breakpoint(address) {
+                return Err(format!("no breakpoint at {}", symbols.locate(address)));
+            }
+            return Ok(());
+        }
+        "bps" => {
+            for address in dbg.breakpoints() {
+                println!("{}", symbols.locate(address));
+            }
+            return Ok(());
+        }
+        "key" => {
+            let mut keypad = Keypad::new();
+            match arg {
+                Some("-") => {}
+                Some(key) => {
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .and_then(ChipKey::from_byte)
+                        .ok_or_else(|| format!("not a hex key: {}", key))?;
+                    keypad.press(key);
+                }
+                None => return Err("key needs a hex key or -".to_string()),
+            }
+            dbg.set_keypad(keypad);
+            return Ok(());
+        }
+        "regs" => {
+            print_registers(dbg.cpu());
+            return Ok(());
+        }
+        "protect" => {
+            let (kind, protection) = parse_protection(arg, words)?;
+            dbg.cpu_mut().memory_mut().set_protection(kind, protection);
+            return Ok(());
+        }
+        "bt" => {
+            print_backtrace(dbg.cpu(), symbols);
+            return Ok(());
+        }
+        "shot" => {
+            let path = arg.ok_or("shot needs a file name")?;
+            let scale = match words.next() {
+                Some(scale) => scale
+                    .parse()
+                    .map_err(|_| format!("not a scale: {}", scale))?,
+                None => DEFAULT_SCREENSHOT_SCALE,
+            };
+            Screenshot::new()
+                .scale(scale)
+                .save(dbg.cpu().graphics(), path)
+                .map_err(|e| format!("could not save {}: {}", path, e))?;
+            return Ok(());
+        }
+        "smc" => {
+            match arg {
+                Some("on") => dbg.set_break_on_self_modification(true),
+                Some("off") => dbg.set_break_on_self_modification(false),
+                Some(other) => return Err(format!("expected on or off, not {}", other)),
+                None => print_self_modifications(dbg, symbols),
+            }
+            return Ok(());
+        }
+        "help" | "h" | "?" => {
+            println!("{}", HELP);
+            return Ok(());
+        }
+        "q" | "quit" => return Err(String::new()),
+        _ => return Err(format!("unknown command: {} (try help)", command)),
+    }
+    print_location(dbg, symbols);
+    Ok(())
+}
+
+fn report(dbg: &Debugger, reason: StopReason, symbols: &SymbolMap) {
+    match reason {
+        StopReason::Breakpoint(address) => {
+            println!("breakpoint at {}", symbols.locate(address))
+        }
+        StopReason::Fault(e) => {
+            println!("halted at {}: {}", symbols.locate(dbg.cpu().pc()), e)
+        }
+        StopReason::SelfModification(pc, write) => {
+            println!("{}: {}", symbols.describe(pc), write)
+        }
+        StopReason::Finished => {}
+    }
+}
+
+fn print_location(dbg: &Debugger, symbols: &SymbolMap) {
+    print_listing(dbg.cpu(), symbols, dbg.cpu().pc(), 1);
+}
+
+/// Disassembles `count` instructions from `start`, with a line for each
+/// label.
+fn print_listing(cpu: &Cpu, symbols: &SymbolMap, start: usize, count: usize) {
+    let mut address = start;
+    for _ in 0..count {
+        if let Some(label) = symbols.label_at(address) {
+            println!("{}:", label);
+        }
+        let opcode = match cpu.read_word(address) {
+            Ok(opcode) => opcode,
+            Err(_) => break,
+        };
+        let instruction = Instruction::decode(opcode);
+        println!(
+            "{:03X}: {:04X}  {}",
+            address,
+            opcode,
+            symbols.labelled(instruction)
+        );
+        address += instruction.size();
+    }
+}
+
+fn print_registers(cpu: &Cpu) {
+    let registers = cpu.registers();
+    for (x, vx) in registers.v.iter().enumerate() {
+        print!("V{:X}={:02X}{}", x, vx, if x % 8 == 7 { "\n" } else { " " });
+    }
+    println!(
+        "I={:03X} DT={:02X} ST={:02X} PC={:03X}",
+        registers.ri,
+        registers.dt,
+        registers.st,
+        cpu.pc()
+    );
+}
+
+fn print_backtrace(cpu: &Cpu, symbols: &SymbolMap) {
+    let memory = cpu.memory();
+    println!("#0 {}", symbols.locate(cpu.pc()));
+    for (level, address) in memory.frames().enumerate() {
+        // Return addresses point just past the call.
+        println!("#{} {}", level + 1, symbols.locate(address.wrapping_sub(2)));
+    }
+    println!(
+        "{} of {} stack levels used",
+        memory.frames().count(),
+        memory.stack_depth()
+    );
+}
+
+fn print_self_modifications(dbg: &Debugger, symbols: &SymbolMap) {
+    let mut any = false;
+    for (pc, write, count) in dbg.self_modifications() {
+        println!("{}: {} ({}x)", symbols.describe(pc), write, count);
+        any = true;
+    }
+    if !any {
+        println!("no writes over executed code");
+    }
+    println!(
+        "stopping on them is {}",
+        if dbg.breaks_on_self_modification() {
+            "on"
+        } else {
+            "off"
+        }
+    );
+}
+
+fn parse_protection<'a>(
+    region: Option<&str>,
+    mut words: impl Iterator<Item = &'a str>,
+) -> Result<(RegionKind, Protection), String> {
+    let kind = match region {
+        Some("interpreter") => RegionKind::Interpreter,
+        Some("font") => RegionKind::Font,
+        Some("program") => RegionKind::Program,
+        Some("stack") => RegionKind::Stack,
+        Some("display") => RegionKind::Display,
+        _ => return Err("expected interpreter, font, program, stack or display".to_string()),
+    };
+    let protection = match words.next() {
+        Some("rw") => Protection::ReadWrite,
+        Some("log") => Protection::Log,
+        Some("fault") => Protection::Fault,
+        _ => return Err("expected rw, log or fault".to_string()),
+    };
+    Ok((kind, protection))
+}
+
+fn parse_count(arg: Option<&str>) -> Result<usize, String> {
+    match arg {
+        Some(n) => n.parse().map_err(|_| format!("not a count: {}", n)),
+        None => Ok(1),
+    }
+}
+
+fn parse_address(arg: Option<&str>, symbols: &SymbolMap) -> Result<usize, String> {
+    let arg = arg.ok_or("expected an address")?;
+    symbols
+        .resolve(arg)
+        .ok_or_else(|| format!("not an address or label: {}", arg))
+}
+
+fn fail_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
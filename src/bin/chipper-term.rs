@@ -0,0 +1,586 @@
+//! Plays a ROM inside a terminal, for machines without a display.
+//!
+//! ```text
+//! chipper-term [--braille] [--strict] [--ipf <n> | --vip] [--display-wait] [--wrap-sprites]
+//!              [--keys <layout> | --keymap <file>] [--gamepad <profile>]
+//!              [--database <programs.json> | --no-database] [--config-dir <dir>] [--trace <file>] [--record <anim.gif|dir>]
+//!              [--blend <frames>] <rom>
+//! ```
+//!
+//! ROMs found in the database by their SHA-1 hash run on their platform, at
+//! their speed and with their quirks, and get the arrow keys, Space and Enter
+//! bound to their D-pad, A and B keys. `--database` adds the entries of a
+//! chip-8-database `programs.json`, and `--no-database` skips the lookup.
+//! A config file named after the ROM, next to it or in `--config-dir`,
+//! overrides the database, and options override both; see `src/config.rs`.
+//!
+//! `--keys` takes a layout (`qwerty`, `azerty`, `dvorak`, `numpad` or `hex`)
+//! or 16 host keys read row by row onto the hex keypad, so the default
+//! `1234qwerasdfzxcv` maps `1234` to `123C`, `qwer` to `456D` and so on.
+//! `--keymap` loads a keymap file, which can set keys per ROM; see
+//! `src/keymap.rs`.
+//!
+//! ROMs the database gives no buttons for take them from the built-in
+//! gamepad profile matching their file name, or from the profile named by
+//! `--gamepad`, which overrides the database too. See `src/gamepad.rs`.
+//!
+//! `--vip` runs as many instructions per frame as the COSMAC VIP would
+//! instead of a fixed `--ipf`. `--display-wait` makes `Dxyn` wait for the
+//! next frame, as the VIP interpreter does, and `--wrap-sprites` wraps
+//! sprites around the screen edges instead of clipping them.
+//!
+//! `--trace` writes an execution trace of every instruction to a file.
+//! `--record` saves every frame where the screen changed as a GIF, or as
+//! numbered PNGs in a directory, on exit. `--blend` shows pixels lit in any
+//! of the last few frames, which hides the flicker of sprites being erased
+//! and redrawn.
+//! Writes outside the program region are listed on exit, or halt the ROM
+//! with `--strict`.
+//! Hold Backspace to rewind, and press Esc or Ctrl-C to quit.
+
+use chipper::config::RomConfig;
+use chipper::cpu::{Cpu, CpuError};
+use chipper::database::Database;
+use chipper::gamepad::Profiles;
+use chipper::graphics::{Graphics, GRAPHICS_HEIGHT, GRAPHICS_WIDTH};
+use chipper::keymap::{HostKey, KeyboardInput, Keymap, KeymapConfig, Layout};
+use chipper::memory::{Protection, RegionKind, Violation, CHIP8_RAM, PROGRAM_START};
+use chipper::phosphor::{Persistence, Phosphor};
+use chipper::recording::Recording;
+use chipper::rewind::History;
+use chipper::runtime::{
+    DEFAULT_INSTRUCTIONS_PER_FRAME, FRAMES_PER_SECOND, FRAME_DURATION, MAX_CATCH_UP_FRAMES,
+};
+use chipper::screenshot::Screenshot;
+use chipper::timing::{Timing, VipClock};
+use chipper::trace::TraceWriter;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers,
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, queue, style, terminal};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+use std::{env, fs, process};
+
+// Without key release events a press is held for this many frames, which
+// bridges the gap until the terminal starts auto-repeating.
+const KEY_HOLD_FRAMES: u32 = 8;
+const REWIND_SECONDS: usize = 30;
+const MAX_REPORTED_VIOLATIONS: usize = 20;
+const RECORD_SCALE: usize = 4;
+
+struct Options {
+    rom: String,
+    braille: bool,
+    strict: bool,
+    timing: Timing,
+    /// Settings given as options, and once the ROM is loaded the settings
+    /// from every layer.
+    config: RomConfig,
+    keymap: Option<Keymap>,
+    keymap_file: Option<String>,
+    database: Option<String>,
+    no_database: bool,
+    config_dir: Option<String>,
+    trace: Option<String>,
+    record: Option<String>,
+    blend: Option<usize>,
+}
+
+struct TerminalGuard {
+    enhanced: bool,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        if enhanced {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(TerminalGuard { enhanced })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn main() {
+    let mut options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!(
+                "usage: chipper-term [--braille] [--strict] [--ipf <n> | --vip] [--display-wait] \
+                 [--wrap-sprites] [--keys <layout> | --keymap <file>] [--gamepad <profile>] \
+                 [--database <programs.json> | --no-database] [--config-dir <dir>] \
+                 [--trace <file>] [--record <anim.gif|dir>] [--blend <frames>] <rom>"
+            );
+            process::exit(2);
+        }
+    };
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("could not read {}: {}", options.rom, e);
+            process::exit(1);
+        }
+    };
+    if rom.len() > CHIP8_RAM - PROGRAM_START {
+        eprintln!("{} is too large to fit in memory", options.rom);
+        process::exit(1);
+    }
+
+    if let Some(path) = &options.keymap_file {
+        let config = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| KeymapConfig::parse(&text).map_err(|e| e.to_string()));
+        match config {
+            Ok(config) => {
+                let name = Path::new(&options.rom).file_name().unwrap_or_default();
+                options.keymap = Some(config.keymap_for(&name.to_string_lossy()));
+            }
+            Err(e) => {
+                eprintln!("could not load {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let database = if options.no_database {
+        Database::new()
+    } else {
+        Database::builtin_with(options.database.as_ref()).unwrap_or_else(|e| {
+            let path = options.database.as_deref().unwrap_or_default();
+            eprintln!("could not load {}: {}", path, e);
+            process::exit(1);
+        })
+    };
+    let config_dir = options.config_dir.as_ref().map(Path::new);
+    let user = std::mem::take(&mut options.config);
+    options.config = RomConfig::for_rom(Path::new(&options.rom), &rom, &database, config_dir, user)
+        .unwrap_or_else(|e| {
+            eprintln!("could not load {}", e);
+            process::exit(1);
+        });
+    if options.keymap.is_none() {
+        options.keymap = Some(options.config.keymap());
+    }
+
+    let mut cpu = options.config.cpu_builder().build();
+    cpu.load_rom(&rom);
+    if options.strict {
+        for &kind in [
+            RegionKind::Interpreter,
+            RegionKind::Font,
+            RegionKind::Stack,
+            RegionKind::Display,
+        ]
+        .iter()
+        {
+            cpu.memory_mut().set_protection(kind, Protection::Fault);
+        }
+    }
+    if let Some(path) = &options.trace {
+        match TraceWriter::create(path) {
+            Ok(tracer) => cpu.set_tracer(Box::new(tracer)),
+            Err(e) => {
+                eprintln!("could not create {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    let mut violations = Vec::new();
+    let mut recording = Recording::new();
+    let outcome = run(&mut cpu, &options, &mut violations, &mut recording);
+    if let Some(path) = &options.record {
+        let screenshot = Screenshot::new()
+            .scale(RECORD_SCALE)
+            .palette(options.config.palette.unwrap_or_default());
+        if let Err(e) = recording.save(&screenshot, path) {
+            eprintln!("could not write {}: {}", path, e);
+        }
+    }
+    for (pc, violation) in violations.iter() {
+        eprintln!("warning: {:03X}: {}", pc, violation);
+    }
+    match outcome {
+        Ok(None) => {}
+        Ok(Some(fault)) => {
+            eprintln!("halted at {:#05X}: {}", cpu.pc(), fault);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("terminal error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Plays until the user quits or the `Cpu` faults, returning the fault.
+fn run(
+    cpu: &mut Cpu,
+    options: &Options,
+    violations: &mut Vec<(usize, Violation)>,
+    recording: &mut Recording,
+) -> io::Result<Option<CpuError>> {
+    let guard = TerminalGuard::enter()?;
+    let mut stdout = io::stdout();
+    let mut last_frame = String::new();
+    let ipf = options
+        .config
+        .instructions_per_frame
+        .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME);
+    let mut history = History::new(REWIND_SECONDS * FRAMES_PER_SECOND as usize, ipf as u64);
+    let mut held_frames = 0;
+    let mut rewind_frames = 0;
+    let mut rewinding = false;
+    let mut was_sounding = false;
+    let mut deadline = Instant::now();
+    let mut clock = VipClock::new();
+    let keymap = options
+        .keymap
+        .clone()
+        .unwrap_or_else(|| Layout::Qwerty.keymap());
+    let mut input = KeyboardInput::new(keymap);
+    let mut phosphor = options
+        .blend
+        .map(|frames| Phosphor::new(Persistence::Blend { frames }));
+
+    let max_behind = FRAME_DURATION * MAX_CATCH_UP_FRAMES;
+    loop {
+        deadline += FRAME_DURATION;
+        // After a stall, such as being suspended, drop the frames the
+        // runtime would drop rather than running them all in a burst.
+        let now = Instant::now();
+        if now.saturating_duration_since(deadline) > max_behind {
+            deadline = now - max_behind;
+        }
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            if is_quit(&key) {
+                return Ok(None);
+            }
+            if key.code == KeyCode::Backspace {
+                rewinding = key.kind != KeyEventKind::Release;
+                rewind_frames = KEY_HOLD_FRAMES;
+                continue;
+            }
+            let host = match host_key(&key) {
+                Some(host) => host,
+                None => continue,
+            };
+            if key.kind == KeyEventKind::Release {
+                input.key_up(host);
+            } else {
+                // Without release events only the latest key is held.
+                if !guard.enhanced {
+                    input.release_all();
+                }
+                if input.key_down(host) {
+                    held_frames = KEY_HOLD_FRAMES;
+                }
+            }
+            let mut keypad = *cpu.keyboard();
+            input.apply(&mut keypad);
+            history.set_keypad(cpu, keypad);
+        }
+
+        if !guard.enhanced {
+            if held_frames > 0 {
+                held_frames -= 1;
+                if held_frames == 0 {
+                    let mut keypad = *cpu.keyboard();
+                    input.release_all();
+                    input.apply(&mut keypad);
+                    history.set_keypad(cpu, keypad);
+                }
+            }
+            if rewind_frames > 0 {
+                rewind_frames -= 1;
+                rewinding = rewind_frames > 0;
+            }
+        }
+
+        if rewinding {
+            history.rewind_frames(cpu, 1);
+            if let Some(phosphor) = phosphor.as_mut() {
+                phosphor.reset();
+            }
+        } else {
+            let mut tick = |cpu: &mut Cpu| {
+                let pc = cpu.pc();
+                history.tick(cpu);
+                if let Some(violation) = cpu.take_violation() {
+                    if violations.len() < MAX_REPORTED_VIOLATIONS {
+                        violations.push((pc, violation));
+                    }
+                }
+            };
+            match options.timing {
+                Timing::Fixed => {
+                    for _ in 0..ipf {
+                        tick(cpu);
+                    }
+                }
+                Timing::Vip => clock.run_frame(cpu, tick),
+            }
+            history.tick_timers(cpu);
+            if let Some(fault) = cpu.fault() {
+                return Ok(Some(fault));
+            }
+        }
+
+        if options.record.is_some() {
+            recording.capture(cpu.graphics());
+        }
+
+        let sounding = cpu.is_sound_playing();
+        if sounding && !was_sounding {
+            queue!(stdout, style::Print('\x07'))?;
+        }
+        was_sounding = sounding;
+
+        let blended = phosphor.as_mut().map(|phosphor| {
+            phosphor.update(cpu.graphics());
+            phosphor.to_graphics(1)
+        });
+        let screen = blended.as_ref().unwrap_or_else(|| cpu.graphics());
+        let frame = if options.braille {
+            render_braille(screen)
+        } else {
+            render_half_blocks(screen)
+        };
+        if frame != last_frame {
+            queue!(stdout, cursor::MoveTo(0, 0), style::Print(&frame))?;
+            last_frame = frame;
+        }
+        stdout.flush()?;
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut braille = false;
+    let mut strict = false;
+    let mut timing = Timing::Fixed;
+    let mut config = RomConfig::default();
+    let mut keymap = None;
+    let mut keymap_file = None;
+    let mut database = None;
+    let mut no_database = false;
+    let mut config_dir = None;
+    let mut trace = None;
+    let mut record = None;
+    let mut blend = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--braille" => braille = true,
+            "--strict" => strict = true,
+            "--vip" => timing = Timing::Vip,
+            "--display-wait" => config.quirks.display_wait = Some(true),
+            "--wrap-sprites" => config.quirks.wrap_sprites = Some(true),
+            "--ipf" => {
+                let value = args.next().ok_or("--ipf needs a value")?;
+                config.instructions_per_frame = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid instructions per frame: {}", value))?,
+                );
+            }
+            "--keys" => {
+                let value = args.next().ok_or("--keys needs a value")?;
+                keymap = Some(parse_keys(&value)?);
+            }
+            "--keymap" => keymap_file = Some(args.next().ok_or("--keymap needs a value")?),
+            "--gamepad" => {
+                let value = args.next().ok_or("--gamepad needs a value")?;
+                let profiles = Profiles::builtin();
+                let profile = profiles
+                    .named(&value)
+                    .ok_or_else(|| format!("unknown gamepad profile: {}", value))?;
+                config.buttons = Some(profile.buttons.clone());
+            }
+            "--database" => database = Some(args.next().ok_or("--database needs a value")?),
+            "--no-database" => no_database = true,
+            "--config-dir" => config_dir = Some(args.next().ok_or("--config-dir needs a value")?),
+            "--trace" => trace = Some(args.next().ok_or("--trace needs a value")?),
+            "--record" => record = Some(args.next().ok_or("--record needs a value")?),
+            "--blend" => {
+                let value = args.next().ok_or("--blend needs a value")?;
+                blend = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid number of frames: {}", value))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+
+    if keymap.is_some() && keymap_file.is_some() {
+        return Err("--keys and --keymap cannot be combined".to_string());
+    }
+    if database.is_some() && no_database {
+        return Err("--database and --no-database cannot be combined".to_string());
+    }
+
+    Ok(Options {
+        rom: rom.ok_or("no ROM given")?,
+        braille,
+        strict,
+        timing,
+        config,
+        keymap,
+        keymap_file,
+        database,
+        no_database,
+        config_dir,
+        trace,
+        record,
+        blend,
+    })
+}
+
+/// Reads `--keys`: a layout name or 16 keys read row by row.
+fn parse_keys(value: &str) -> Result<Keymap, String> {
+    match Layout::parse(value) {
+        Some(layout) => Ok(layout.keymap()),
+        None => Keymap::from_rows(value).map_err(|e| format!("{}: {}", value, e)),
+    }
+}
+
+fn host_key(key: &KeyEvent) -> Option<HostKey> {
+    let numpad = key.state.contains(KeyEventState::KEYPAD);
+    Some(match key.code {
+        KeyCode::Char(c) if numpad => HostKey::Numpad(c),
+        KeyCode::Char(c) => HostKey::from_char(c),
+        KeyCode::Enter if numpad => HostKey::NumpadEnter,
+        KeyCode::Enter => HostKey::Enter,
+        KeyCode::Up => HostKey::Up,
+        KeyCode::Down => HostKey::Down,
+        KeyCode::Left => HostKey::Left,
+        KeyCode::Right => HostKey::Right,
+        _ => return None,
+    })
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind != KeyEventKind::Release
+        && (key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)))
+}
+
+/// Draws two pixel rows per line of text using the upper and lower half
+/// block characters.
+fn render_half_blocks(gfx: &Graphics) -> String {
+    let mut out = String::new();
+    for y in (0..GRAPHICS_HEIGHT).step_by(2) {
+        for x in 0..GRAPHICS_WIDTH {
+            out.push(match (gfx.is_pixel_set(x, y), gfx.is_pixel_set(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Draws a 2x4 block of pixels per character using the braille patterns.
+fn render_braille(gfx: &Graphics) -> String {
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut out = String::new();
+    for y in (0..GRAPHICS_HEIGHT).step_by(4) {
+        for x in (0..GRAPHICS_WIDTH).step_by(2) {
+            let mut pattern = 0;
+            for (dy, row) in DOTS.iter().enumerate() {
+                for (dx, dot) in row.iter().enumerate() {
+                    if gfx.is_pixel_set(x + dx, y + dy) {
+                        pattern |= dot;
+                    }
+                }
+            }
+            out.push(std::char::from_u32(0x2800 + pattern).unwrap_or(' '));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn keys_take_a_layout_or_rows() {
+        assert_eq!(Ok(Layout::Dvorak.keymap()), parse_keys("dvorak"));
+        assert_eq!(
+            Layout::Qwerty.keymap(),
+            parse_keys("1234QWERasdfzxcv").unwrap()
+        );
+        assert!(parse_keys("1234").is_err());
+    }
+
+    #[test]
+    fn options_are_the_top_layer() {
+        let args = ["--ipf", "7", "--display-wait", "tetris.ch8"];
+        let options = parse_args(args.iter().map(|a| a.to_string())).unwrap();
+        assert_eq!(Some(7), options.config.instructions_per_frame);
+        assert_eq!(Some(true), options.config.quirks.display_wait);
+        assert_eq!(None, options.config.quirks.wrap_sprites);
+        assert_eq!(None, options.keymap);
+    }
+
+    #[test]
+    fn gamepad_picks_a_builtin_profile() {
+        let args = ["--gamepad", "tetris", "game.ch8"];
+        let options = parse_args(args.iter().map(|a| a.to_string())).unwrap();
+        let tetris = Profiles::builtin().named("Tetris").unwrap().buttons.clone();
+        assert_eq!(Some(tetris), options.config.buttons);
+        let args = ["--gamepad", "nope", "game.ch8"];
+        assert!(parse_args(args.iter().map(|a| a.to_string())).is_err());
+    }
+
+    #[test]
+    fn half_blocks_pack_two_rows() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(0, 0, &[0x80, 0x40]);
+        let frame = render_half_blocks(&gfx);
+        assert_eq!(GRAPHICS_HEIGHT / 2, frame.lines().count());
+        assert!(frame.starts_with("▀▄ "));
+    }
+
+    #[test]
+    fn braille_packs_eight_pixels() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(0, 0, &[0xC0, 0xC0, 0xC0, 0xC0]);
+        assert!(render_braille(&gfx).starts_with('⣿'));
+    }
+}
@@ -0,0 +1,226 @@
+//! Runs a ROM headlessly and checks the final screen.
+//!
+//! ```text
+//! chipper-test [--frames <n>] [--ipf <n> | --vip] [--display-wait] [--wrap-sprites]
+//!              [--press <frame>:<key>]... [--release <frame>]...
+//!              [--expect <image.pbm|png> | --hash <hex>]
+//!              [--write <image.pbm|png>] [--record <anim.gif|dir>]
+//!              [--scale <n>] [--palette <off>:<on>]
+//!              [--database <programs.json> | --no-database] [--config-dir <dir>] <rom>
+//! ```
+//!
+//! The ROM runs with the same settings as in `chipper-term`: its database
+//! entry, then its config file, then the options; see `src/config.rs`.
+//! Keys are hex digits. `--display-wait` makes `Dxyn` wait for the next
+//! frame, as on the COSMAC VIP, and `--wrap-sprites` wraps sprites around
+//! the screen edges instead of clipping them. `--record` saves every frame where the
+//! screen changed as a GIF, or as numbered PNGs in a directory. `--scale` and
+//! `--palette` change the images written by `--write` and `--record`.
+//!
+//! Prints the screen's hash, and exits with status 0 when the screen
+//! matches, 1 when it does not or the ROM faulted, and 2 on bad arguments or
+//! files.
+
+use chipper::config::RomConfig;
+use chipper::database::Database;
+use chipper::harness::Harness;
+use chipper::keypad::ChipKey;
+use chipper::recording::Recording;
+use chipper::screenshot::{self, Palette, Screenshot};
+use chipper::timing::Timing;
+use std::path::Path;
+use std::{env, fs, process};
+
+const DEFAULT_FRAMES: u32 = 60;
+const USAGE: &str = "usage: chipper-test [--frames <n>] [--ipf <n> | --vip] [--display-wait] \
+                     [--wrap-sprites] [--press <frame>:<key>]... [--release <frame>]... \
+                     [--expect <image.pbm|png> | --hash <hex>] [--write <image.pbm|png>] \
+                     [--record <anim.gif|dir>] [--scale <n>] [--palette <off>:<on>] \
+                     [--database <programs.json> | --no-database] [--config-dir <dir>] <rom>";
+
+enum Expectation {
+    Image(String),
+    Hash(u64),
+}
+
+fn main() {
+    let mut frames = DEFAULT_FRAMES;
+    let mut presses = Vec::new();
+    let mut releases = Vec::new();
+    let mut user = RomConfig::default();
+    let mut vip = false;
+    let mut database_file = None;
+    let mut no_database = false;
+    let mut config_dir = None;
+    let mut expectation = None;
+    let mut write = None;
+    let mut record = None;
+    let mut image = Screenshot::new();
+    let mut rom = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = number(args.next(), "--frames"),
+            "--ipf" => user.instructions_per_frame = Some(number(args.next(), "--ipf")),
+            "--vip" => vip = true,
+            "--display-wait" => user.quirks.display_wait = Some(true),
+            "--wrap-sprites" => user.quirks.wrap_sprites = Some(true),
+            "--press" => {
+                let value = args.next().unwrap_or_default();
+                presses.push(
+                    parse_press(&value)
+                        .unwrap_or_else(|| fail_usage("--press needs <frame>:<key>, e.g. 30:a")),
+                )
+            }
+            "--release" => releases.push(number(args.next(), "--release")),
+            "--expect" => match args.next() {
+                Some(path) => expectation = Some(Expectation::Image(path)),
+                None => fail_usage("--expect needs a file"),
+            },
+            "--hash" => match args.next().and_then(|h| u64::from_str_radix(&h, 16).ok()) {
+                Some(hash) => expectation = Some(Expectation::Hash(hash)),
+                None => fail_usage("--hash needs a hex number"),
+            },
+            "--write" => match args.next() {
+                Some(path) => write = Some(path),
+                None => fail_usage("--write needs a file"),
+            },
+            "--record" => match args.next() {
+                Some(path) => record = Some(path),
+                None => fail_usage("--record needs a file or directory"),
+            },
+            "--scale" => image = image.scale(number(args.next(), "--scale") as usize),
+            "--palette" => match args.next().as_deref().and_then(Palette::parse) {
+                Some(palette) => image = image.palette(palette),
+                None => fail_usage("--palette needs two hex colours, e.g. 000000:33ff66"),
+            },
+            "--database" => match args.next() {
+                Some(path) => database_file = Some(path),
+                None => fail_usage("--database needs a file"),
+            },
+            "--no-database" => no_database = true,
+            "--config-dir" => match args.next() {
+                Some(dir) => config_dir = Some(dir),
+                None => fail_usage("--config-dir needs a directory"),
+            },
+            _ if arg.starts_with("--") => fail_usage(&format!("unknown option: {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => fail_usage("expected one ROM"),
+        }
+    }
+    if vip && user.instructions_per_frame.is_some() {
+        fail_usage("--ipf and --vip cannot be combined");
+    }
+    if database_file.is_some() && no_database {
+        fail_usage("--database and --no-database cannot be combined");
+    }
+    let rom = rom.unwrap_or_else(|| fail_usage("expected a ROM"));
+    let bytes = read(&rom);
+
+    let database = if no_database {
+        Database::new()
+    } else {
+        Database::builtin_with(database_file.as_ref()).unwrap_or_else(|e| {
+            eprintln!(
+                "could not load {}: {}",
+                database_file.unwrap_or_default(),
+                e
+            );
+            process::exit(2);
+        })
+    };
+    let config_dir = config_dir.as_deref().map(Path::new);
+    let config = RomConfig::for_rom(Path::new(&rom), &bytes, &database, config_dir, user)
+        .unwrap_or_else(|e| {
+            eprintln!("could not load {}", e);
+            process::exit(2);
+        });
+
+    let mut harness = Harness::new(frames).cpu(config.cpu_builder());
+    if let Some(n) = config.instructions_per_frame {
+        harness = harness.instructions_per_frame(n);
+    }
+    if vip {
+        harness = harness.timing(Timing::Vip);
+    }
+    for (frame, key) in presses {
+        harness = harness.press(frame, key);
+    }
+    for frame in releases {
+        harness = harness.release(frame);
+    }
+
+    let mut recording = Recording::new();
+    let run = match record {
+        Some(_) => harness.record(&bytes, &mut recording),
+        None => harness.run(&bytes),
+    };
+    let run = run.unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom, e);
+        process::exit(2);
+    });
+    if let Some(path) = record {
+        if let Err(e) = recording.save(&image, &path) {
+            eprintln!("could not write {}: {}", path, e);
+            process::exit(2);
+        }
+    }
+    if let Some(path) = write {
+        if let Err(e) = image.save(&run.graphics, &path) {
+            eprintln!("could not write {}: {}", path, e);
+            process::exit(2);
+        }
+    }
+    println!("hash {:016x}", run.hash());
+
+    let mut passed = true;
+    if let Some(fault) = &run.fault {
+        println!("fault: {}", fault);
+        passed = false;
+    }
+    match expectation {
+        Some(Expectation::Image(path)) => {
+            let expected = screenshot::load(&path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                process::exit(2);
+            });
+            if let Err(mismatch) = run.compare(&expected) {
+                print!("{}", mismatch);
+                passed = false;
+            }
+        }
+        Some(Expectation::Hash(hash)) if hash != run.hash() => {
+            println!("expected hash {:016x}", hash);
+            passed = false;
+        }
+        _ => {}
+    }
+    if !passed {
+        process::exit(1);
+    }
+}
+
+fn parse_press(value: &str) -> Option<(u32, ChipKey)> {
+    let (frame, key) = value.split_once(':')?;
+    let key = u8::from_str_radix(key, 16).ok()?;
+    Some((frame.parse().ok()?, ChipKey::from_byte(key)?))
+}
+
+fn number(value: Option<String>, option: &str) -> u32 {
+    value
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| fail_usage(&format!("{} needs a number", option)))
+}
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn fail_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,67 @@
+//! Reports where two execution traces first disagree.
+//!
+//! ```text
+//! chipper-tracediff [--context <n>] <ours> <theirs>
+//! ```
+//!
+//! Exits with status 0 when the traces agree on every step and 1 when they
+//! diverge, including when one ends before the other. See
+//! `src/trace_diff.rs` for the accepted trace format.
+
+use chipper::trace_diff::{first_divergence, parse_trace, TraceLine};
+use std::fs::File;
+use std::io::BufReader;
+use std::{env, process};
+
+const DEFAULT_CONTEXT: usize = 5;
+const USAGE: &str = "usage: chipper-tracediff [--context <n>] <ours> <theirs>";
+
+fn main() {
+    let mut context = DEFAULT_CONTEXT;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                context = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => n,
+                    None => fail_usage("--context needs a number"),
+                }
+            }
+            _ if arg.starts_with("--") => fail_usage(&format!("unknown option: {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        fail_usage("expected two trace files");
+    }
+
+    let ours = read_trace(&paths[0]);
+    let theirs = read_trace(&paths[1]);
+    match first_divergence(&ours, &theirs, context) {
+        Some(divergence) => {
+            println!("{}", divergence);
+            process::exit(1);
+        }
+        None => {
+            println!("traces agree over {} steps", ours.len().max(theirs.len()));
+        }
+    }
+}
+
+fn read_trace(path: &str) -> Vec<TraceLine> {
+    let file = File::open(path).unwrap_or_else(|e| {
+        eprintln!("could not open {}: {}", path, e);
+        process::exit(2);
+    });
+    parse_trace(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn fail_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,436 @@
+//! Per-ROM settings, layered from several places.
+//!
+//! Each layer overrides the settings of the ones before it:
+//!
+//! 1. chipper's defaults and those of the ROM's variant,
+//! 2. the ROM's entry in the [`Database`](crate::database::Database),
+//! 3. the ROM's config file, `<rom>.toml` or `<rom>.json` next to the ROM
+//!    or in a config directory,
+//! 4. settings given by the user, such as command line options.
+//!
+//! [`RomConfig::layered`] merges them in this order,
+//! [`RomConfig::for_rom`] also finds the database entry and config file, and
+//! [`cpu_builder`](RomConfig::cpu_builder) turns the result into a
+//! [`CpuBuilder`]. A config file sets any of:
+//!
+//! ```toml
+//! variant = "originalChip8"
+//! instructions_per_frame = 15
+//! palette = "000000:33ff66"
+//! font = "vip"
+//! layout = "azerty"
+//!
+//! [quirks]
+//! display_wait = false
+//!
+//! [keys]
+//! 5 = ["space"]
+//! ```
+//!
+//! Variants are named by their chip-8-database platform and keys as in
+//! keymap files. JSON files have the same fields.
+
+use crate::cpu::CpuBuilder;
+use crate::database::{Database, RomInfo};
+use crate::gamepad::{Button, ButtonMap, Profiles};
+use crate::keymap::{self, HostKey, Keymap, KeymapError, Layout};
+use crate::keypad::ChipKey;
+use crate::memory::Font;
+use crate::quirks::{QuirkOverrides, Quirks};
+use crate::screenshot::Palette;
+use crate::variant::Variant;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+// Host keys bound to a ROM's game buttons, on top of the layout.
+const BUTTON_KEYS: [(Button, HostKey); 6] = [
+    (Button::Up, HostKey::Up),
+    (Button::Down, HostKey::Down),
+    (Button::Left, HostKey::Left),
+    (Button::Right, HostKey::Right),
+    (Button::A, HostKey::Char(' ')),
+    (Button::B, HostKey::Enter),
+];
+
+/// One layer of settings. Anything left unset falls through to the layers
+/// below.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomConfig {
+    pub variant: Option<Variant>,
+    pub quirks: QuirkOverrides,
+    pub instructions_per_frame: Option<u32>,
+    pub palette: Option<Palette>,
+    pub font: Option<Font>,
+    pub layout: Option<Layout>,
+    /// Game buttons whose keys the arrow keys, Space and Enter press.
+    pub buttons: Option<ButtonMap>,
+    /// Keypad keys bound to exactly these host keys.
+    pub keys: Vec<(ChipKey, Vec<HostKey>)>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownVariant(String),
+    UnknownFont(String),
+    BadPalette(String),
+    Keymap(KeymapError),
+    Syntax(String),
+    Io(io::Error),
+    /// An error in the config file at the path.
+    File(PathBuf, Box<ConfigError>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    variant: Option<String>,
+    #[serde(default)]
+    quirks: RawQuirks,
+    instructions_per_frame: Option<u32>,
+    palette: Option<String>,
+    font: Option<String>,
+    layout: Option<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawQuirks {
+    display_wait: Option<bool>,
+    wrap_sprites: Option<bool>,
+}
+
+impl RomConfig {
+    /// The settings a database entry gives. Only the quirks the ROM changes
+    /// from its variant's are set, so a later layer changing the variant
+    /// gets that variant's quirks.
+    pub fn from_rom_info(info: &RomInfo) -> Self {
+        let quirks = match info.variant {
+            Some(variant) => QuirkOverrides::between(Quirks::for_variant(variant), info.quirks),
+            None => QuirkOverrides::default(),
+        };
+        RomConfig {
+            variant: info.variant,
+            quirks,
+            instructions_per_frame: info.tickrate,
+            palette: info.palette,
+            buttons: info.buttons.clone(),
+            ..RomConfig::default()
+        }
+    }
+
+    pub fn parse_toml(text: &str) -> Result<Self, ConfigError> {
+        let raw: RawConfig =
+            toml::from_str(text).map_err(|e| ConfigError::Syntax(e.message().to_string()))?;
+        RomConfig::from_raw(raw)
+    }
+
+    pub fn parse_json(text: &str) -> Result<Self, ConfigError> {
+        let raw: RawConfig =
+            serde_json::from_str(text).map_err(|e| ConfigError::Syntax(e.to_string()))?;
+        RomConfig::from_raw(raw)
+    }
+
+    /// Reads a config file, as JSON if its name ends in `.json` and as TOML
+    /// otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if is_json {
+            RomConfig::parse_json(&text)
+        } else {
+            RomConfig::parse_toml(&text)
+        }
+    }
+
+    /// The config file for the ROM at `rom`: `<rom>.toml` or `<rom>.json`
+    /// beside it, or else in `dir`.
+    pub fn find(rom: &Path, dir: Option<&Path>) -> Option<PathBuf> {
+        let stem = rom.file_stem()?;
+        let beside = rom.parent().unwrap_or_else(|| Path::new(""));
+        std::iter::once(beside)
+            .chain(dir)
+            .flat_map(|dir| {
+                ["toml", "json"].iter().map(move |extension| {
+                    let mut name = stem.to_os_string();
+                    name.push(".");
+                    name.push(extension);
+                    dir.join(name)
+                })
+            })
+            .find(|path| path.is_file())
+    }
+
+    /// Merges the database entry, config file and user settings over the
+    /// defaults, in that order.
+    pub fn layered(database: Option<&RomInfo>, file: Option<RomConfig>, user: RomConfig) -> Self {
+        let mut config = RomConfig::default();
+        if let Some(info) = database {
+            config.merge(RomConfig::from_rom_info(info));
+        }
+        if let Some(file) = file {
+            config.merge(file);
+        }
+        config.merge(user);
+        config
+    }
+
+    /// The settings for `rom`, read from the file at `path`: its entry in
+    /// `database` and its config file, found by [`RomConfig::find`], layered
+    /// under `user`. ROMs left without game buttons get them from the
+    /// built-in gamepad profile for their file name.
+    pub fn for_rom(
+        path: &Path,
+        rom: &[u8],
+        database: &Database,
+        dir: Option<&Path>,
+        user: RomConfig,
+    ) -> Result<Self, ConfigError> {
+        let file = match RomConfig::find(path, dir) {
+            Some(file) => {
+                Some(RomConfig::load(&file).map_err(|e| ConfigError::File(file, Box::new(e)))?)
+            }
+            None => None,
+        };
+        let mut config = RomConfig::layered(database.lookup(rom), file, user);
+        if config.buttons.is_none() {
+            config.buttons = Profiles::builtin()
+                .for_rom(path)
+                .map(|profile| profile.buttons.clone());
+        }
+        Ok(config)
+    }
+
+    /// Takes the settings `over` sets, keeping the rest. Its keys are bound
+    /// after these.
+    pub fn merge(&mut self, over: RomConfig) {
+        self.variant = over.variant.or(self.variant);
+        self.quirks.merge(over.quirks);
+        self.instructions_per_frame = over.instructions_per_frame.or(self.instructions_per_frame);
+        self.palette = over.palette.or(self.palette);
+        self.font = over.font.or(self.font);
+        self.layout = over.layout.or(self.layout);
+        self.buttons = over.buttons.or(self.buttons.take());
+        self.keys.extend(over.keys);
+    }
+
+    pub fn cpu_builder(&self) -> CpuBuilder {
+        CpuBuilder::new()
+            .variant(self.variant.unwrap_or_default())
+            .quirk_overrides(self.quirks)
+            .font(self.font.unwrap_or_default())
+    }
+
+    /// The layout, QWERTY if unset, with the game buttons and then the keys
+    /// bound on top.
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = self.layout.unwrap_or(Layout::Qwerty).keymap();
+        if let Some(buttons) = &self.buttons {
+            for &(button, host) in BUTTON_KEYS.iter() {
+                if let Some(key) = buttons.get(button) {
+                    keymap.bind(host, key);
+                }
+            }
+        }
+        for (key, hosts) in self.keys.iter() {
+            keymap.rebind(*key, hosts);
+        }
+        keymap
+    }
+
+    fn from_raw(raw: RawConfig) -> Result<Self, ConfigError> {
+        let variant = match raw.variant {
+            Some(id) => Some(Variant::from_platform(&id).ok_or(ConfigError::UnknownVariant(id))?),
+            None => None,
+        };
+        let palette = match raw.palette {
+            Some(text) => Some(Palette::parse(&text).ok_or(ConfigError::BadPalette(text))?),
+            None => None,
+        };
+        let font = match raw.font {
+            Some(name) => Some(Font::parse(&name).ok_or(ConfigError::UnknownFont(name))?),
+            None => None,
+        };
+        let layout = match raw.layout {
+            Some(name) => Some(
+                Layout::parse(&name)
+                    .ok_or(ConfigError::Keymap(KeymapError::UnknownLayout(name)))?,
+            ),
+            None => None,
+        };
+        Ok(RomConfig {
+            variant,
+            quirks: QuirkOverrides {
+                display_wait: raw.quirks.display_wait,
+                wrap_sprites: raw.quirks.wrap_sprites,
+            },
+            instructions_per_frame: raw.instructions_per_frame,
+            palette,
+            font,
+            layout,
+            buttons: None,
+            keys: keymap::parse_bindings(raw.keys).map_err(ConfigError::Keymap)?,
+        })
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownVariant(id) => write!(f, "unknown variant: {}", id),
+            ConfigError::UnknownFont(name) => write!(f, "unknown font: {}", name),
+            ConfigError::BadPalette(text) => {
+                write!(f, "palette must be two hex colours as off:on, not {}", text)
+            }
+            ConfigError::Keymap(e) => write!(f, "{}", e),
+            ConfigError::Syntax(message) => write!(f, "{}", message),
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::File(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use pretty_assertions::assert_eq;
+
+    fn tetris() -> RomInfo {
+        Database::builtin()
+            .get("5f518084744bf3cb8733f6e5454dfd1634320563")
+            .cloned()
+            .unwrap()
+    }
+
+    #[test]
+    fn toml_and_json_read_the_same_settings() {
+        let toml = RomConfig::parse_toml(
+            r#"
+            variant = "originalChip8"
+            instructions_per_frame = 15
+            palette = "000000:33ff66"
+            font = "vip"
+
+            [quirks]
+            display_wait = false
+
+            [keys]
+            5 = ["space"]
+            "#,
+        )
+        .unwrap();
+        let json = RomConfig::parse_json(
+            r#"{
+                "variant": "originalChip8",
+                "instructions_per_frame": 15,
+                "palette": "000000:33ff66",
+                "font": "vip",
+                "quirks": {"display_wait": false},
+                "keys": {"5": ["space"]}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(toml, json);
+
+        let cpu = toml.cpu_builder();
+        assert_eq!(Quirks::default(), cpu.resolved_quirks());
+        assert_eq!(Some(ChipKey::CK_5), toml.keymap().get(HostKey::Char(' ')));
+        assert_eq!(None, toml.keymap().get(HostKey::Char('w')));
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = RomConfig::parse_toml("instructions_per_frame = 20\nlayout = \"hex\"").unwrap();
+        let user = RomConfig {
+            quirks: QuirkOverrides {
+                wrap_sprites: Some(true),
+                ..QuirkOverrides::default()
+            },
+            ..RomConfig::default()
+        };
+        let config = RomConfig::layered(Some(&tetris()), Some(file), user);
+        assert_eq!(Some(Variant::SuperChip), config.variant);
+        assert_eq!(Some(20), config.instructions_per_frame);
+        assert!(config.cpu_builder().resolved_quirks().wrap_sprites);
+
+        let keymap = config.keymap();
+        assert_eq!(Some(ChipKey::CK_4), keymap.get(HostKey::Char('4')));
+        assert_eq!(Some(ChipKey::CK_5), keymap.get(HostKey::Left));
+    }
+
+    #[test]
+    fn changing_the_variant_drops_its_quirks() {
+        let mut info = tetris();
+        info.variant = Some(Variant::OriginalChip8);
+        info.quirks = Quirks::for_variant(Variant::OriginalChip8);
+        let file = RomConfig::parse_toml("variant = \"modernChip8\"").unwrap();
+        let config = RomConfig::layered(Some(&info), Some(file), RomConfig::default());
+        assert_eq!(Quirks::default(), config.cpu_builder().resolved_quirks());
+    }
+
+    #[test]
+    fn for_rom_finds_every_layer() {
+        let dir = std::env::temp_dir().join(format!("chipper-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pong.toml"), "instructions_per_frame = 12").unwrap();
+        fs::write(dir.join("broken.toml"), "variant = 3").unwrap();
+        let user = RomConfig {
+            variant: Some(Variant::XoChip),
+            ..RomConfig::default()
+        };
+
+        let config = RomConfig::for_rom(
+            &dir.join("pong.ch8"),
+            &[0x00, 0xE0],
+            &Database::builtin(),
+            None,
+            user,
+        )
+        .unwrap();
+        assert_eq!(Some(12), config.instructions_per_frame);
+        assert_eq!(Some(Variant::XoChip), config.variant);
+        let pong = Profiles::builtin().named("Pong").unwrap().buttons.clone();
+        assert_eq!(Some(pong), config.buttons);
+
+        let broken = RomConfig::for_rom(
+            Path::new("roms/broken.ch8"),
+            &[],
+            &Database::new(),
+            Some(&dir),
+            RomConfig::default(),
+        );
+        assert!(
+            matches!(broken, Err(ConfigError::File(path, _)) if path == dir.join("broken.toml"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_reports_bad_values() {
+        assert!(matches!(
+            RomConfig::parse_toml("variant = \"chip8x\""),
+            Err(ConfigError::UnknownVariant(_))
+        ));
+        assert!(matches!(
+            RomConfig::parse_toml("font = \"fish\""),
+            Err(ConfigError::UnknownFont(_))
+        ));
+        assert!(matches!(
+            RomConfig::parse_toml("[keys]\n5 = [\"escape\"]"),
+            Err(ConfigError::Keymap(KeymapError::UnknownHostKey(_)))
+        ));
+        assert!(matches!(
+            RomConfig::parse_json("{\"speed\": 3}"),
+            Err(ConfigError::Syntax(_))
+        ));
+    }
+}
@@ -0,0 +1,318 @@
+//! Splits a ROM's reachable code into basic blocks and exports the graph.
+//!
+//! A [`FlowGraph`] is built from the same walk as `analysis`. Blocks start at
+//! `0x200`, at every jump, call and skip target, at the address after a call,
+//! and after any instruction that does not simply fall through. Edges are
+//! labelled with how control moves between them; a subroutine's `00EE`
+//! returns to the instruction after every call of that subroutine. `Bnnn`
+//! blocks have no outgoing edges since their targets are only known at run
+//! time.
+
+use crate::analysis::{instruction_at, successors, Flow};
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use crate::symbols::SymbolMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// The block's instructions with their addresses, in order.
+    pub instructions: Vec<(usize, Instruction)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Falling through to the next instruction, including not skipping.
+    Next,
+    Jump,
+    Call,
+    Return,
+    /// Skipping the next instruction.
+    Skip,
+}
+
+/// An edge between the blocks starting at `from` and `to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlowGraph {
+    /// By start address.
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: Vec<Edge>,
+    /// Start addresses of the blocks `2nnn` calls.
+    pub subroutines: BTreeSet<usize>,
+}
+
+impl Block {
+    /// The address after the block's last instruction.
+    pub fn end(&self) -> usize {
+        self.instructions
+            .last()
+            .map_or(self.start, |&(address, instruction)| {
+                address + instruction.size()
+            })
+    }
+
+    fn last(&self) -> (usize, Instruction) {
+        *self.instructions.last().expect("blocks are not empty")
+    }
+}
+
+impl FlowGraph {
+    pub fn build(rom: &[u8]) -> Self {
+        let mut reachable = BTreeMap::new();
+        let mut leaders = BTreeSet::from([PROGRAM_START]);
+        let mut pending = vec![PROGRAM_START];
+        while let Some(address) = pending.pop() {
+            if reachable.contains_key(&address) {
+                continue;
+            }
+            let instruction = match instruction_at(rom, address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            reachable.insert(address, instruction);
+            let targets = successors(rom, address, instruction);
+            if Flow::of(instruction) != Flow::Next {
+                leaders.extend(targets.iter().copied());
+                leaders.insert(address + instruction.size());
+            }
+            pending.extend(targets);
+        }
+
+        let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for (&address, &instruction) in reachable.iter() {
+            let continues = current
+                .as_ref()
+                .is_some_and(|block| block.end() == address && !leaders.contains(&address));
+            if !continues {
+                if let Some(block) = current.take() {
+                    blocks.insert(block.start, block);
+                }
+                current = Some(Block {
+                    start: address,
+                    instructions: Vec::new(),
+                });
+            }
+            let block = current.as_mut().expect("a block was started");
+            block.instructions.push((address, instruction));
+            if Flow::of(instruction) != Flow::Next {
+                blocks.insert(block.start, current.take().expect("a block was started"));
+            }
+        }
+        if let Some(block) = current {
+            blocks.insert(block.start, block);
+        }
+
+        let mut graph = FlowGraph {
+            blocks,
+            edges: Vec::new(),
+            subroutines: BTreeSet::new(),
+        };
+        graph.add_edges(rom);
+        graph
+    }
+
+    fn add_edges(&mut self, rom: &[u8]) {
+        // Where each subroutine returns to.
+        let mut return_sites: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for block in self.blocks.values() {
+            let (address, instruction) = block.last();
+            let next = address + instruction.size();
+            let targets = successors(rom, address, instruction);
+            let kinds: &[EdgeKind] = match Flow::of(instruction) {
+                Flow::Next => &[EdgeKind::Next],
+                Flow::Jump(_) => &[EdgeKind::Jump],
+                Flow::Skip => &[EdgeKind::Next, EdgeKind::Skip],
+                Flow::Call(target) => {
+                    self.subroutines.insert(target);
+                    return_sites.entry(target).or_default().push(next);
+                    &[EdgeKind::Call]
+                }
+                Flow::Return | Flow::Indirect | Flow::Halt => &[],
+            };
+            for (&to, &kind) in targets.iter().zip(kinds) {
+                if self.blocks.contains_key(&to) {
+                    self.edges.push(Edge {
+                        from: block.start,
+                        to,
+                        kind,
+                    });
+                }
+            }
+        }
+
+        for (&entry, sites) in return_sites.iter() {
+            for ret in self.returns_of(entry) {
+                for &site in sites.iter() {
+                    if self.blocks.contains_key(&site) {
+                        self.edges.push(Edge {
+                            from: ret,
+                            to: site,
+                            kind: EdgeKind::Return,
+                        });
+                    }
+                }
+            }
+        }
+        self.edges.sort_unstable();
+        self.edges.dedup();
+    }
+
+    // The blocks ending in `00EE` reachable from `entry` without leaving the
+    // subroutine. Calls made from it are assumed to return.
+    fn returns_of(&self, entry: usize) -> Vec<usize> {
+        let mut returns = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let block = match self.blocks.get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let (_, instruction) = block.last();
+            match Flow::of(instruction) {
+                Flow::Return => returns.push(start),
+                Flow::Call(_) => pending.push(block.end()),
+                _ => pending.extend(
+                    self.edges
+                        .iter()
+                        .filter(|edge| edge.from == start)
+                        .map(|edge| edge.to),
+                ),
+            }
+        }
+        returns.sort_unstable();
+        returns
+    }
+
+    /// The graph in Graphviz DOT, with one box per block listing its
+    /// disassembly. Subroutines are drawn in bold.
+    pub fn to_dot(&self) -> String {
+        self.to_labelled_dot(&SymbolMap::new())
+    }
+
+    /// Like [`FlowGraph::to_dot`], with the disassembly using the labels in
+    /// `symbols` and each label on its own line before its address.
+    pub fn to_labelled_dot(&self, symbols: &SymbolMap) -> String {
+        let mut dot = String::new();
+        self.write_dot(&mut dot, symbols)
+            .expect("writing to a String");
+        dot
+    }
+
+    fn write_dot(&self, out: &mut String, symbols: &SymbolMap) -> fmt::Result {
+        writeln!(out, "digraph rom {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks.values() {
+            write!(out, "    \"{:03X}\" [label=\"", block.start)?;
+            for &(address, instruction) in block.instructions.iter() {
+                if let Some(label) = symbols.label_at(address) {
+                    write!(out, "{}:\\l", label)?;
+                }
+                write!(out, "{:03X}: {}\\l", address, symbols.labelled(instruction))?;
+            }
+            write!(out, "\"")?;
+            if self.subroutines.contains(&block.start) {
+                write!(out, ", style=bold")?;
+            }
+            writeln!(out, "];")?;
+        }
+        for edge in self.edges.iter() {
+            write!(out, "    \"{:03X}\" -> \"{:03X}\"", edge.from, edge.to)?;
+            match edge.kind {
+                EdgeKind::Next => {}
+                EdgeKind::Jump => write!(out, " [label=\"jump\"]")?,
+                EdgeKind::Call => write!(out, " [label=\"call\", color=blue]")?,
+                EdgeKind::Return => write!(out, " [label=\"return\", style=dashed]")?,
+                EdgeKind::Skip => write!(out, " [label=\"skip\"]")?,
+            }
+            writeln!(out, ";")?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ROM: [u8; 18] = [
+        0x22, 0x0A, // 200: CALL 20A
+        0x22, 0x0A, // 202: CALL 20A
+        0xE1, 0x9E, // 204: SKP V1
+        0x12, 0x04, // 206: JP 204
+        0x00, 0xFD, // 208: EXIT
+        0x60, 0x01, // 20A: LD V0, 1
+        0x30, 0x01, // 20C: SE V0, 1
+        0x00, 0xEE, // 20E: RET
+        0x00, 0xEE, // 210: RET
+    ];
+
+    fn edge(from: usize, to: usize, kind: EdgeKind) -> Edge {
+        Edge { from, to, kind }
+    }
+
+    #[test]
+    fn splits_blocks_at_targets_and_transfers() {
+        let graph = FlowGraph::build(&ROM);
+        assert_eq!(
+            vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20E, 0x210],
+            graph.blocks.keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(0x20E, graph.blocks[&0x20A].end());
+        assert_eq!(BTreeSet::from([0x20A]), graph.subroutines);
+    }
+
+    #[test]
+    fn links_calls_returns_and_skips() {
+        let graph = FlowGraph::build(&ROM);
+        assert_eq!(
+            vec![
+                edge(0x200, 0x20A, EdgeKind::Call),
+                edge(0x202, 0x20A, EdgeKind::Call),
+                edge(0x204, 0x206, EdgeKind::Next),
+                edge(0x204, 0x208, EdgeKind::Skip),
+                edge(0x206, 0x204, EdgeKind::Jump),
+                edge(0x20A, 0x20E, EdgeKind::Next),
+                edge(0x20A, 0x210, EdgeKind::Skip),
+                edge(0x20E, 0x202, EdgeKind::Return),
+                edge(0x20E, 0x204, EdgeKind::Return),
+                edge(0x210, 0x202, EdgeKind::Return),
+                edge(0x210, 0x204, EdgeKind::Return),
+            ],
+            graph.edges
+        );
+    }
+
+    #[test]
+    fn exports_dot() {
+        let dot = FlowGraph::build(&ROM).to_dot();
+        assert!(dot.starts_with("digraph rom {\n"));
+        assert!(dot.contains(
+            "    \"20A\" [label=\"20A: LD V0, 0x01\\l20C: SE V0, 0x01\\l\", style=bold];\n"
+        ));
+        assert!(dot.contains("    \"20E\" -> \"202\" [label=\"return\", style=dashed];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn labels_dot_blocks() {
+        let symbols = SymbolMap::parse("flicker = 0x20A").unwrap();
+        let dot = FlowGraph::build(&ROM).to_labelled_dot(&symbols);
+        assert!(dot.contains("    \"200\" [label=\"200: CALL flicker\\l\"];\n"));
+        assert!(dot.contains("    \"20A\" [label=\"flicker:\\l20A: LD V0, 0x01\\l"));
+    }
+}
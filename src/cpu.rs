@@ -1,12 +1,30 @@
 use crate::graphics::Graphics;
+#[cfg(feature = "std")]
+use crate::instruction::DecodeCache;
+use crate::instruction::Instruction;
 use crate::keypad::{ChipKey, Keypad};
-use crate::memory::Memory;
-use rand::prelude::*;
+use crate::memory::{
+    Font, Memory, MemoryError, SelfModification, StackError, Violation, FONT_ADDRESS,
+    FONT_SPRITE_SIZE, PROGRAM_START,
+};
+use crate::quirks::{QuirkOverrides, Quirks};
+use crate::timing;
+#[cfg(feature = "std")]
+use crate::trace::{MemoryWrite, TraceRecord, Tracer};
+use crate::variant::Variant;
+use core::fmt;
+use rand::rngs::StdRng;
+use rand::RngCore;
+#[cfg(feature = "std")]
+use rand::SeedableRng;
 use PcResult::*;
 
 const INSTRUCTION_SIZE: usize = 2;
 
-pub struct Cpu {
+/// The interpreter. Random numbers for `Cxkk` come from `R`, which is seeded
+/// from the operating system by `Cpu::new`; without the `std` feature pass
+/// a generator to `Cpu::with_rng` instead.
+pub struct Cpu<R = StdRng> {
     pc: usize,
     ri: usize,
     v: [u8; 16],
@@ -15,7 +33,73 @@ pub struct Cpu {
     memory: Memory,
     keyboard: Keypad,
     graphics: Graphics,
-    random: StdRng,
+    random: R,
+    fault: Option<CpuError>,
+    cycles: u64,
+    quirks: Quirks,
+    display: DisplayWait,
+    #[cfg(feature = "std")]
+    tracer: Option<Box<dyn Tracer>>,
+    #[cfg(feature = "std")]
+    trace_step: u64,
+    #[cfg(feature = "std")]
+    trace_writes: Vec<MemoryWrite>,
+    #[cfg(feature = "std")]
+    decoded: Option<DecodeCache>,
+}
+
+/// Builds a `Cpu` for a variant, with its quirks and font changed as needed.
+///
+/// Settings can be given more than once and the last one wins, so layer them
+/// from the least to the most specific: see
+/// [`RomConfig`](crate::config::RomConfig) for the order chipper uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuBuilder {
+    variant: Variant,
+    quirks: QuirkOverrides,
+    font: Font,
+}
+
+/// Everything needed to put a `Cpu` back into an earlier state. Tracers are
+/// not part of a snapshot.
+#[derive(Clone)]
+pub struct Snapshot<R = StdRng> {
+    pub(crate) pc: usize,
+    pub(crate) registers: Registers,
+    pub(crate) memory: Memory,
+    pub(crate) keyboard: Keypad,
+    pub(crate) graphics: Graphics,
+    pub(crate) random: R,
+    pub(crate) fault: Option<CpuError>,
+    pub(crate) display: DisplayWait,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub ri: usize,
+    pub dt: u8,
+    pub st: u8,
+}
+
+/// Why the `Cpu` stopped executing instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    Stack(StackError),
+    Memory(MemoryError),
+    /// An opcode that does not decode, or comes from an extension the
+    /// interpreter does not run.
+    UnsupportedInstruction(u16),
+}
+
+/// How far a `Dxyn` has got in waiting for the next frame, with
+/// `Quirks::display_wait`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DisplayWait {
+    Idle,
+    Waiting,
+    /// A new frame has started and the waiting `Dxyn` can draw.
+    Ready,
 }
 
 enum PcResult {
@@ -23,12 +107,13 @@ enum PcResult {
     Hop,
     Skip,
     Jump(usize),
+    Fault(CpuError),
 }
 
 impl PcResult {
     fn apply(&self, pc: usize) -> usize {
         match self {
-            Self::Wait => pc,
+            Self::Wait | Self::Fault(_) => pc,
             Self::Hop => pc + INSTRUCTION_SIZE,
             Self::Skip => pc + 2 * INSTRUCTION_SIZE,
             Self::Jump(n) => *n,
@@ -36,17 +121,72 @@ impl PcResult {
     }
 }
 
+#[cfg(feature = "std")]
 impl Default for Cpu {
     fn default() -> Self {
         Cpu::new(Memory::new(), Keypad::new(), Graphics::new())
     }
 }
 
-#[allow(non_snake_case)]
+#[cfg(feature = "std")]
 impl Cpu {
     pub fn new(memory: Memory, keyboard: Keypad, graphics: Graphics) -> Self {
+        Cpu::with_rng(memory, keyboard, graphics, StdRng::from_entropy())
+    }
+}
+
+impl CpuBuilder {
+    pub fn new() -> Self {
+        CpuBuilder::default()
+    }
+
+    /// Sets the variant, whose quirks apply unless overridden.
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Overrides every quirk.
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = QuirkOverrides::all(quirks);
+        self
+    }
+
+    /// Overrides the quirks `overrides` sets.
+    pub fn quirk_overrides(mut self, overrides: QuirkOverrides) -> Self {
+        self.quirks.merge(overrides);
+        self
+    }
+
+    pub fn font(mut self, font: Font) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// The quirks the `Cpu` will run with.
+    pub fn resolved_quirks(&self) -> Quirks {
+        self.quirks.apply(Quirks::for_variant(self.variant))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn build(&self) -> Cpu {
+        self.build_with_rng(StdRng::from_entropy())
+    }
+
+    pub fn build_with_rng<R: RngCore>(&self, random: R) -> Cpu<R> {
+        let mut memory = Memory::for_variant(self.variant);
+        memory.set_font(self.font);
+        let mut cpu = Cpu::with_rng(memory, Keypad::new(), Graphics::new(), random);
+        cpu.set_quirks(self.resolved_quirks());
+        cpu
+    }
+}
+
+#[allow(non_snake_case)]
+impl<R: RngCore> Cpu<R> {
+    pub fn with_rng(memory: Memory, keyboard: Keypad, graphics: Graphics, random: R) -> Self {
         Cpu {
-            pc: 0x200,
+            pc: PROGRAM_START,
             ri: 0,
             v: [0; 16],
             dt: 0,
@@ -54,85 +194,314 @@ impl Cpu {
             memory,
             keyboard,
             graphics,
-            random: StdRng::from_entropy(),
+            random,
+            fault: None,
+            cycles: 0,
+            quirks: Quirks::default(),
+            display: DisplayWait::Idle,
+            #[cfg(feature = "std")]
+            tracer: None,
+            #[cfg(feature = "std")]
+            trace_step: 0,
+            #[cfg(feature = "std")]
+            trace_writes: Vec::new(),
+            #[cfg(feature = "std")]
+            decoded: Some(DecodeCache::new()),
         }
     }
 
-    fn tick(&mut self) {
-        let bytes = self.memory.load(self.pc, INSTRUCTION_SIZE);
-        let instruction = ((bytes[0] as u16) << 8) | (bytes[1] as u16);
-        self.decode_execute(instruction);
-    }
-
-    fn decode_execute(&mut self, instruction: u16) {
-        let nibbles = Self::unpack_nibbles(instruction);
-        let nnn = (instruction & 0x0FFF) as usize;
-        let kk = (instruction & 0x00FF) as u8;
-        let x = nibbles.1;
-        let y = nibbles.2;
-        let n = nibbles.3;
-
-        let pc_result = match nibbles {
-            (0x0, 0x0, 0xC, _)
-            | (0x0, 0x0, 0xF, 0xB)
-            | (0x0, 0x0, 0xF, 0xC)
-            | (0x0, 0x0, 0xF, 0xD)
-            | (0x0, 0x0, 0xF, 0xE)
-            | (0x0, 0x0, 0xF, 0xF)
-            | (0xD, _, _, 0x0)
-            | (0xF, _, 0x3, 0x0)
-            | (0xF, _, 0x7, 0x5)
-            | (0xF, _, 0x8, 0x5) => panic!(
-                "Super CHIP-48 instruction not implemented: {:#06x}",
-                instruction
-            ),
-            (0x0, 0x0, 0xE, 0x0) => self.op_00E0(),
-            (0x0, 0x0, 0xE, 0xE) => self.op_00EE(),
-            (0x0, _, _, _) => self.op_0nnn(nnn),
-            (0x1, _, _, _) => self.op_1nnn(nnn),
-            (0x2, _, _, _) => self.op_2nnn(nnn),
-            (0x3, _, _, _) => self.op_3xkk(x, kk),
-            (0x4, _, _, _) => self.op_4xkk(x, kk),
-            (0x5, _, _, 0x0) => self.op_5xy0(x, y),
-            (0x6, _, _, _) => self.op_6xkk(x, kk),
-            (0x7, _, _, _) => self.op_7xkk(x, kk),
-            (0x8, _, _, 0x0) => self.op_8xy0(x, y),
-            (0x8, _, _, 0x1) => self.op_8xy1(x, y),
-            (0x8, _, _, 0x2) => self.op_8xy2(x, y),
-            (0x8, _, _, 0x3) => self.op_8xy3(x, y),
-            (0x8, _, _, 0x4) => self.op_8xy4(x, y),
-            (0x8, _, _, 0x5) => self.op_8xy5(x, y),
-            (0x8, _, _, 0x6) => self.op_8xy6(x, y),
-            (0x8, _, _, 0x7) => self.op_8xy7(x, y),
-            (0x8, _, _, 0xE) => self.op_8xyE(x, y),
-            (0x9, _, _, 0x0) => self.op_9xy0(x, y),
-            (0xA, _, _, _) => self.op_Annn(nnn),
-            (0xB, _, _, _) => self.op_Bnnn(nnn),
-            (0xC, _, _, _) => self.op_Cxkk(x, kk),
-            (0xD, _, _, _) => self.op_Dxyn(x, y, n),
-            (0xE, _, 0x9, 0xE) => self.op_Ex9E(x),
-            (0xE, _, 0xA, 0x1) => self.op_ExA1(x),
-            (0xF, _, 0x0, 0x7) => self.op_Fx07(x),
-            (0xF, _, 0x0, 0xA) => self.op_Fx0A(x),
-            (0xF, _, 0x1, 0x5) => self.op_Fx15(x),
-            (0xF, _, 0x1, 0x8) => self.op_Fx18(x),
-            (0xF, _, 0x1, 0xE) => self.op_Fx1E(x),
-            (0xF, _, 0x3, 0x3) => self.op_Fx33(x),
-            (0xF, _, 0x5, 0x5) => self.op_Fx55(x),
-            (0xF, _, 0x6, 0x5) => self.op_Fx65(x),
-            _ => panic!("Unknown opcode: {:#06x}", instruction),
+    /// Copies `rom` into memory at the start of the program region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rom` does not fit between `PROGRAM_START` and the end of RAM.
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        self.memory.store(PROGRAM_START, rom);
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter.
+    /// Does nothing once the `Cpu` has faulted.
+    pub fn tick(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+        let pc = self.pc;
+        let instruction = match self.fetch(pc) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                self.fault = Some(CpuError::Memory(e));
+                return;
+            }
         };
+        self.memory.mark_executed(pc);
+        #[cfg(feature = "std")]
+        if self.tracer.is_some() {
+            self.execute_traced(pc, instruction);
+            return;
+        }
+        self.execute(instruction);
+    }
+
+    #[cfg(feature = "std")]
+    fn execute_traced(&mut self, pc: usize, instruction: Instruction) {
+        let opcode = self.read_word(pc).expect("the instruction was fetched");
+        let before = self.registers();
+        self.execute(instruction);
+        let record = TraceRecord {
+            step: self.trace_step,
+            pc,
+            opcode,
+            before,
+            after: self.registers(),
+            writes: std::mem::take(&mut self.trace_writes),
+        };
+        self.trace_step += 1;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&record);
+        }
+    }
 
-        self.pc = pc_result.apply(self.pc);
+    /// Starts reporting every executed instruction to `tracer`, with step
+    /// numbers counted from zero.
+    #[cfg(feature = "std")]
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+        self.trace_step = 0;
+    }
+
+    #[cfg(feature = "std")]
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    /// Puts back a tracer taken with `take_tracer`, carrying on with the
+    /// step numbers where they left off.
+    #[cfg(feature = "std")]
+    pub(crate) fn resume_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Reads the big-endian word at `address` the way instructions are
+    /// fetched.
+    pub fn read_word(&self, address: usize) -> Result<u16, MemoryError> {
+        self.memory.read_word(address)
+    }
+
+    /// Turns the decode cache on or off. It is on by default and only worth
+    /// turning off to compare against uncached decoding.
+    #[cfg(feature = "std")]
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = if enabled {
+            Some(DecodeCache::new())
+        } else {
+            None
+        };
+    }
+
+    #[cfg(feature = "std")]
+    fn fetch(&mut self, address: usize) -> Result<Instruction, MemoryError> {
+        let written = self.memory.take_written();
+        match self.decoded.as_mut() {
+            Some(cache) => {
+                if let Some(written) = written {
+                    cache.invalidate(written);
+                }
+                cache.fetch(&self.memory, address)
+            }
+            None => self.memory.read_word(address).map(Instruction::decode),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn fetch(&mut self, address: usize) -> Result<Instruction, MemoryError> {
+        self.memory.read_word(address).map(Instruction::decode)
     }
 
-    fn unpack_nibbles(instruction: u16) -> (usize, usize, usize, usize) {
-        (
-            ((instruction & 0xF000) >> 12) as usize,
-            ((instruction & 0x0F00) >> 8) as usize,
-            ((instruction & 0x00F0) >> 4) as usize,
-            (instruction & 0x000F) as usize,
-        )
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.display = DisplayWait::Idle;
+    }
+
+    /// COSMAC VIP machine cycles the executed instructions would have taken.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            ri: self.ri,
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot<R>
+    where
+        R: Clone,
+    {
+        Snapshot {
+            pc: self.pc,
+            registers: self.registers(),
+            memory: self.memory.clone(),
+            keyboard: self.keyboard,
+            graphics: self.graphics.clone(),
+            random: self.random.clone(),
+            fault: self.fault,
+            display: self.display,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot<R>)
+    where
+        R: Clone,
+    {
+        let registers = snapshot.registers;
+        self.pc = snapshot.pc;
+        self.v = registers.v;
+        self.ri = registers.ri;
+        self.dt = registers.dt;
+        self.st = registers.st;
+        // What has run as code is kept, so rewinding does not forget it, and
+        // protection is the host's setting rather than emulated state.
+        let code = self.memory.code_map();
+        let protection = self.memory.protections();
+        self.memory = snapshot.memory.clone();
+        self.memory.set_code_map(code);
+        self.memory.set_protections(protection);
+        self.keyboard = snapshot.keyboard;
+        self.graphics = snapshot.graphics.clone();
+        self.random = snapshot.random.clone();
+        self.fault = snapshot.fault;
+        self.display = snapshot.display;
+        #[cfg(feature = "std")]
+        if let Some(cache) = self.decoded.as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Decrements the delay and sound timers and lets a `Dxyn` waiting for
+    /// the display draw. Hosts call this at 60 Hz, at the start of a frame.
+    pub fn tick_timers(&mut self) {
+        if self.display == DisplayWait::Waiting {
+            self.display = DisplayWait::Ready;
+        }
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+
+    pub fn is_sound_playing(&self) -> bool {
+        self.st > 0
+    }
+
+    pub fn graphics(&self) -> &Graphics {
+        &self.graphics
+    }
+
+    /// The error that halted execution, if any.
+    pub fn fault(&self) -> Option<CpuError> {
+        self.fault
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Takes the most recent logged write into a protected memory region.
+    pub fn take_violation(&mut self) -> Option<Violation> {
+        self.memory.take_violation()
+    }
+
+    /// Takes the most recent write by the program over code it has already
+    /// executed.
+    pub fn take_self_modification(&mut self) -> Option<SelfModification> {
+        self.memory.take_self_modification()
+    }
+
+    pub fn keyboard(&self) -> &Keypad {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keypad {
+        &mut self.keyboard
+    }
+
+    #[cfg(test)]
+    fn decode_execute(&mut self, opcode: u16) {
+        self.execute(Instruction::decode(opcode));
+    }
+
+    fn execute(&mut self, instruction: Instruction) {
+        use Instruction::*;
+        let (v, ri) = (self.v, self.ri);
+        let pc_result = match instruction {
+            Scd(_) | Scr | Scl | Exit | Low | High | DrwHigh(..) | LdHf(_) | LdRVx(_)
+            | LdVxR(_) => self.unsupported(),
+            // Without XO-CHIP, 00Dn is a machine code call like any other 0nnn.
+            Scu(n) => self.op_0nnn(0x0D0 | n),
+            SaveRange(..) | LoadRange(..) | LdILong | Plane(_) | Audio | Pitch(_) => {
+                self.unsupported()
+            }
+            Cls => self.op_00E0(),
+            Ret => self.op_00EE(),
+            Sys(nnn) => self.op_0nnn(nnn),
+            Jp(nnn) => self.op_1nnn(nnn),
+            Call(nnn) => self.op_2nnn(nnn),
+            SeByte(x, kk) => self.op_3xkk(x, kk),
+            SneByte(x, kk) => self.op_4xkk(x, kk),
+            SeReg(x, y) => self.op_5xy0(x, y),
+            LdByte(x, kk) => self.op_6xkk(x, kk),
+            AddByte(x, kk) => self.op_7xkk(x, kk),
+            LdReg(x, y) => self.op_8xy0(x, y),
+            Or(x, y) => self.op_8xy1(x, y),
+            And(x, y) => self.op_8xy2(x, y),
+            Xor(x, y) => self.op_8xy3(x, y),
+            AddReg(x, y) => self.op_8xy4(x, y),
+            Sub(x, y) => self.op_8xy5(x, y),
+            Shr(x, y) => self.op_8xy6(x, y),
+            Subn(x, y) => self.op_8xy7(x, y),
+            Shl(x, y) => self.op_8xyE(x, y),
+            SneReg(x, y) => self.op_9xy0(x, y),
+            LdI(nnn) => self.op_Annn(nnn),
+            JpV0(nnn) => self.op_Bnnn(nnn),
+            Rnd(x, kk) => self.op_Cxkk(x, kk),
+            Drw(x, y, n) => self.op_Dxyn(x, y, n),
+            Skp(x) => self.op_Ex9E(x),
+            Sknp(x) => self.op_ExA1(x),
+            LdVxDt(x) => self.op_Fx07(x),
+            LdVxK(x) => self.op_Fx0A(x),
+            LdDtVx(x) => self.op_Fx15(x),
+            LdStVx(x) => self.op_Fx18(x),
+            AddI(x) => self.op_Fx1E(x),
+            LdF(x) => self.op_Fx29(x),
+            LdB(x) => self.op_Fx33(x),
+            LdIVx(x) => self.op_Fx55(x),
+            LdVxI(x) => self.op_Fx65(x),
+            Unknown(opcode) => Fault(CpuError::UnsupportedInstruction(opcode)),
+        };
+
+        if let Fault(e) = pc_result {
+            self.fault = Some(e);
+        }
+        // A sprite waiting for the display is only charged once it draws.
+        let waiting = self.display == DisplayWait::Waiting;
+        if !waiting {
+            let skipped = matches!(pc_result, Skip);
+            self.cycles += timing::vip_cycles(instruction, &v, ri, skipped) as u64;
+        }
+        self.pc = pc_result.apply(self.pc);
     }
 
     fn op_00E0(&mut self) -> PcResult {
@@ -142,8 +511,8 @@ impl Cpu {
 
     fn op_00EE(&mut self) -> PcResult {
         match self.memory.pop() {
-            Some(n) => Jump(n),
-            None => Hop,
+            Ok(n) => Jump(n),
+            Err(e) => Fault(CpuError::Stack(e)),
         }
     }
 
@@ -158,8 +527,10 @@ impl Cpu {
     }
 
     fn op_2nnn(&mut self, address: usize) -> PcResult {
-        self.memory.push(self.pc + INSTRUCTION_SIZE);
-        Jump(address)
+        match self.memory.push(self.pc + INSTRUCTION_SIZE) {
+            Ok(()) => Jump(address),
+            Err(e) => Fault(CpuError::Stack(e)),
+        }
     }
 
     fn op_3xkk(&mut self, x: usize, kk: u8) -> PcResult {
@@ -251,10 +622,25 @@ impl Cpu {
     }
 
     fn op_Dxyn(&mut self, x: usize, y: usize, n: usize) -> PcResult {
-        let sprite = self.memory.load(self.ri as usize, n);
-        let collision =
-            self.graphics
-                .draw_with_collision(self.v[x] as usize, self.v[y] as usize, &sprite);
+        if self.quirks.display_wait {
+            match self.display {
+                DisplayWait::Idle | DisplayWait::Waiting => {
+                    self.display = DisplayWait::Waiting;
+                    return Wait;
+                }
+                DisplayWait::Ready => self.display = DisplayWait::Idle,
+            }
+        }
+        let sprite = match self.memory.slice(self.ri, n) {
+            Ok(sprite) => sprite,
+            Err(e) => return Fault(CpuError::Memory(e)),
+        };
+        let (x, y) = (self.v[x] as usize, self.v[y] as usize);
+        let collision = if self.quirks.wrap_sprites {
+            self.graphics.draw_wrapping(x, y, sprite)
+        } else {
+            self.graphics.draw_with_collision(x, y, sprite)
+        };
         self.overflow_flag(collision);
         Hop
     }
@@ -297,24 +683,49 @@ impl Cpu {
         Hop
     }
 
+    fn op_Fx29(&mut self, x: usize) -> PcResult {
+        self.ri = FONT_ADDRESS + (self.v[x] & 0xF) as usize * FONT_SPRITE_SIZE;
+        Hop
+    }
+
     fn op_Fx33(&mut self, x: usize) -> PcResult {
         let vx = self.v[x];
         let bcd = &[(vx / 100) % 10, (vx / 10) % 10, vx % 10];
-        self.memory.store(self.ri, bcd);
-        Hop
+        self.store_memory(self.ri, bcd)
     }
 
     fn op_Fx55(&mut self, x: usize) -> PcResult {
-        self.memory.store(self.ri, &self.v[0..x + 1]);
-        Hop
+        let regs = self.v;
+        self.store_memory(self.ri, &regs[0..x + 1])
     }
 
     fn op_Fx65(&mut self, x: usize) -> PcResult {
-        let regs = self.memory.load(self.ri, x + 1);
-        self.v.copy_from_slice(&regs);
+        match self.memory.slice(self.ri, x + 1) {
+            Ok(regs) => self.v[..=x].copy_from_slice(regs),
+            Err(e) => return Fault(CpuError::Memory(e)),
+        }
+        Hop
+    }
+
+    fn store_memory(&mut self, offset: usize, data: &[u8]) -> PcResult {
+        if let Err(e) = self.memory.write(offset, data) {
+            return Fault(CpuError::Memory(e));
+        }
+        #[cfg(feature = "std")]
+        if self.tracer.is_some() {
+            self.trace_writes.push(MemoryWrite {
+                address: offset,
+                bytes: data.to_vec(),
+            });
+        }
         Hop
     }
 
+    fn unsupported(&self) -> PcResult {
+        let opcode = self.memory.read_word(self.pc).unwrap_or_default();
+        Fault(CpuError::UnsupportedInstruction(opcode))
+    }
+
     fn check_key(&self, src: usize) -> bool {
         match ChipKey::from_byte(self.v[src]) {
             Some(key) => self.keyboard.is_pressed(key),
@@ -349,9 +760,25 @@ impl Cpu {
     }
 }
 
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Stack(e) => write!(f, "{}", e),
+            CpuError::Memory(e) => write!(f, "{}", e),
+            CpuError::UnsupportedInstruction(opcode) => {
+                write!(f, "unsupported instruction {:04X}", opcode)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CpuError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::{Protection, RegionKind};
     use pretty_assertions::assert_eq;
 
     macro_rules! uses {
@@ -469,6 +896,59 @@ mod tests {
         assert_eq!(0x202, cpu.pc);
     }
 
+    #[test]
+    fn ret_with_empty_stack_faults() {
+        uses!(mut cpu);
+        cpu.memory.store(0x200, &[0x00, 0xEE]);
+        cpu.tick();
+        assert_eq!(Some(CpuError::Stack(StackError::Underflow)), cpu.fault());
+        assert_eq!(0x200, cpu.pc);
+        cpu.tick();
+        assert_eq!(0x200, cpu.pc);
+    }
+
+    #[test]
+    fn builder_overrides_variant_quirks_and_font() {
+        let cpu = CpuBuilder::new()
+            .variant(Variant::OriginalChip8)
+            .quirk_overrides(QuirkOverrides {
+                wrap_sprites: Some(true),
+                ..QuirkOverrides::default()
+            })
+            .font(Font::Vip)
+            .build();
+        assert_eq!(
+            Quirks {
+                display_wait: true,
+                wrap_sprites: true
+            },
+            cpu.quirks()
+        );
+        assert_eq!(12, cpu.memory().stack_depth());
+        let one = cpu
+            .memory()
+            .slice(FONT_ADDRESS + FONT_SPRITE_SIZE, FONT_SPRITE_SIZE)
+            .unwrap();
+        assert_eq!(&[0x60, 0x20, 0x20, 0x20, 0x70], one);
+    }
+
+    #[test]
+    fn call_past_stack_depth_faults() {
+        let mut cpu = Cpu::new(
+            Memory::for_variant(Variant::OriginalChip8),
+            Keypad::new(),
+            Graphics::new(),
+        );
+        for _ in 0..12 {
+            cpu.decode_execute(0x2200);
+        }
+        assert_eq!(None, cpu.fault());
+        assert_eq!(12, cpu.memory.frames().count());
+
+        cpu.decode_execute(0x2200);
+        assert_eq!(Some(CpuError::Stack(StackError::Overflow)), cpu.fault());
+    }
+
     #[test]
     fn se_constant_skip() {
         uses!(mut cpu);
@@ -687,6 +1167,30 @@ mod tests {
         assert_eq!(0, cpu.v[0x0]);
     }
 
+    #[test]
+    fn rnd_uses_supplied_generator() {
+        struct Fixed;
+        impl RngCore for Fixed {
+            fn next_u32(&mut self) -> u32 {
+                0xAB
+            }
+            fn next_u64(&mut self) -> u64 {
+                0xAB
+            }
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                dest.fill(0xAB);
+            }
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+                self.fill_bytes(dest);
+                Ok(())
+            }
+        }
+
+        let mut cpu = Cpu::with_rng(Memory::new(), Keypad::new(), Graphics::new(), Fixed);
+        cpu.decode_execute(0xC30F);
+        assert_eq!(0x0B, cpu.v[0x3]);
+    }
+
     #[test]
     fn drw_two_byte_sprite_no_overlap_no_collision() {
         uses!(mut cpu);
@@ -711,6 +1215,64 @@ mod tests {
         assert_eq!(1, cpu.v[0xF]);
     }
 
+    #[test]
+    fn drw_waits_for_next_frame_with_display_wait() {
+        uses!(mut cpu);
+        cpu.set_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+        cpu.ri = FONT_ADDRESS;
+        cpu.decode_execute(0xD005);
+        cpu.decode_execute(0xD005);
+        assert_eq!(0x200, cpu.pc);
+        assert!(!cpu.graphics.is_pixel_set(0, 0));
+
+        cpu.tick_timers();
+        cpu.decode_execute(0xD005);
+        assert_eq!(0x202, cpu.pc);
+        assert!(cpu.graphics.is_pixel_set(0, 0));
+        // The next sprite waits for another frame.
+        cpu.tick_timers();
+        cpu.decode_execute(0xD005);
+        assert_eq!(0x202, cpu.pc);
+    }
+
+    #[test]
+    fn drw_wraps_with_wrap_sprites() {
+        uses!(mut cpu);
+        cpu.memory.store(0x300, &[0xC0]);
+        cpu.ri = 0x300;
+        cpu.v[0x0] = 63 + 64;
+        cpu.decode_execute(0xD011);
+        assert!(cpu.graphics.is_pixel_set(63, 0));
+        assert!(!cpu.graphics.is_pixel_set(0, 0));
+
+        cpu.graphics.clear();
+        cpu.set_quirks(Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        });
+        cpu.decode_execute(0xD011);
+        assert!(cpu.graphics.is_pixel_set(0, 0));
+    }
+
+    #[test]
+    fn drw_waiting_survives_restore() {
+        uses!(mut cpu);
+        cpu.set_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+        cpu.decode_execute(0xD005);
+        let waiting = cpu.snapshot();
+        cpu.tick_timers();
+        cpu.decode_execute(0xD005);
+        cpu.restore(&waiting);
+        cpu.decode_execute(0xD005);
+        assert_eq!(0x200, cpu.pc);
+    }
+
     #[test]
     fn skp_register_keyboard_skip() {
         uses!(mut cpu);
@@ -793,13 +1355,25 @@ mod tests {
         assert_eq!(56, cpu.ri);
     }
 
+    #[test]
+    fn ld_font_sprite_address() {
+        uses!(mut cpu);
+        cpu.v[0x0] = 0xA;
+        cpu.decode_execute(0xF029);
+        assert_eq!(FONT_ADDRESS + 50, cpu.ri);
+        assert_eq!(
+            vec![0xF0, 0x90, 0xF0, 0x90, 0x90],
+            cpu.memory.load(cpu.ri, 5).unwrap()
+        );
+    }
+
     #[test]
     fn ld_bcd_register() {
         uses!(mut cpu);
         cpu.v[0x0] = 123;
         cpu.ri = 0x300;
         cpu.decode_execute(0xF033);
-        let bcd = cpu.memory.load(0x300, 3);
+        let bcd = cpu.memory.load(0x300, 3).unwrap();
         assert_eq!(1, bcd[0]);
         assert_eq!(2, bcd[1]);
         assert_eq!(3, bcd[2]);
@@ -813,10 +1387,117 @@ mod tests {
         cpu.v[0x9] = 2;
         cpu.v[0xF] = 1;
         cpu.decode_execute(0xFF55);
-        let mem = cpu.memory.load(0x300, 16);
+        let mem = cpu.memory.load(0x300, 16).unwrap();
         assert_eq!(vec![23, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 1], mem);
     }
 
+    #[test]
+    fn sd_registers_into_protected_memory_faults() {
+        uses!(mut cpu);
+        cpu.memory
+            .set_protection(RegionKind::Font, Protection::Fault);
+        cpu.ri = FONT_ADDRESS;
+        cpu.decode_execute(0xF055);
+        assert_eq!(
+            Some(CpuError::Memory(MemoryError::Protected(Violation {
+                address: FONT_ADDRESS,
+                len: 1,
+                region: RegionKind::Font,
+            }))),
+            cpu.fault()
+        );
+        assert_eq!(0x200, cpu.pc);
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modified_code() {
+        // Bumps the operand of its own ADD V2 each time around the loop.
+        let rom = [
+            0x72, 0x01, 0xA2, 0x01, 0xF0, 0x65, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00,
+        ];
+        for &cached in [true, false].iter() {
+            uses!(mut cpu);
+            cpu.set_decode_cache(cached);
+            cpu.load_rom(&rom);
+            for _ in 0..18 {
+                cpu.tick();
+            }
+            assert_eq!(1 + 2 + 3, cpu.v[0x2]);
+        }
+    }
+
+    #[test]
+    fn storing_over_executed_code_is_flagged() {
+        uses!(mut cpu);
+        // LD V0, 0x12; LD I, 0x200; LD [I], V0
+        cpu.load_rom(&[0x60, 0x12, 0xA2, 0x00, 0xF0, 0x55]);
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(None, cpu.take_self_modification());
+        cpu.tick();
+        assert_eq!(
+            Some(SelfModification {
+                address: 0x200,
+                len: 1
+            }),
+            cpu.take_self_modification()
+        );
+        assert_eq!(
+            vec![0x200],
+            cpu.memory().self_modified().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn xo_chip_scroll_runs_as_machine_code_call() {
+        let mut cpu = CpuBuilder::new().variant(Variant::ModernChip8).build();
+        cpu.load_rom(&[0x00, 0xD1, 0xF0, 0x00, 0x03, 0x00]);
+        cpu.tick();
+        assert_eq!(0x202, cpu.pc());
+        assert_eq!(None, cpu.fault());
+        cpu.tick();
+        assert_eq!(Some(CpuError::UnsupportedInstruction(0xF000)), cpu.fault());
+    }
+
+    #[test]
+    fn reads_past_end_of_ram_fault() {
+        uses!(mut cpu);
+        cpu.ri = 0xFFC;
+        cpu.decode_execute(0xF565);
+        assert_eq!(
+            Some(CpuError::Memory(MemoryError::OutOfBounds {
+                address: 0xFFC,
+                len: 6
+            })),
+            cpu.fault()
+        );
+
+        uses!(mut cpu);
+        cpu.pc = 0xFFF;
+        cpu.tick();
+        assert_eq!(
+            Some(CpuError::Memory(MemoryError::OutOfBounds {
+                address: 0xFFF,
+                len: 2
+            })),
+            cpu.fault()
+        );
+    }
+
+    #[test]
+    fn bcd_past_end_of_ram_faults() {
+        uses!(mut cpu);
+        cpu.ri = 0xFFE;
+        cpu.decode_execute(0xF033);
+        assert_eq!(
+            Some(CpuError::Memory(MemoryError::OutOfBounds {
+                address: 0xFFE,
+                len: 3
+            })),
+            cpu.fault()
+        );
+    }
+
     #[test]
     fn ld_registers() {
         uses!(mut cpu);
@@ -828,4 +1509,105 @@ mod tests {
         assert_eq!(2, cpu.v[0x9]);
         assert_eq!(1, cpu.v[0xF]);
     }
+
+    #[test]
+    fn ld_registers_partial() {
+        uses!(mut cpu);
+        cpu.ri = 0x300;
+        cpu.v[0x2] = 9;
+        cpu.memory.store(cpu.ri, &[4, 5]);
+        cpu.decode_execute(0xF165);
+        assert_eq!(4, cpu.v[0x0]);
+        assert_eq!(5, cpu.v[0x1]);
+        assert_eq!(9, cpu.v[0x2]);
+    }
+
+    #[test]
+    fn tracer_records_each_tick() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Collect(Rc<RefCell<Vec<TraceRecord>>>);
+        impl Tracer for Collect {
+            fn trace(&mut self, record: &TraceRecord) {
+                self.0.borrow_mut().push(record.clone());
+            }
+        }
+
+        uses!(mut cpu);
+        let records = Rc::new(RefCell::new(Vec::new()));
+        cpu.set_tracer(Box::new(Collect(records.clone())));
+        cpu.memory
+            .store(0x200, &[0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33]);
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+
+        let records = records.borrow();
+        assert_eq!(3, records.len());
+        assert_eq!(2, records[2].step);
+        assert_eq!(0x204, records[2].pc);
+        assert_eq!(0xF033, records[2].opcode);
+        assert_eq!(0x7B, records[0].after.v[0x0]);
+        assert_eq!(
+            vec![MemoryWrite {
+                address: 0x300,
+                bytes: vec![1, 2, 3]
+            }],
+            records[2].writes
+        );
+    }
+
+    #[test]
+    fn restore_snapshot() {
+        uses!(mut cpu);
+        cpu.memory
+            .store(0x200, &[0x60, 0x01, 0xC1, 0xFF, 0x22, 0x00]);
+        let snapshot = cpu.snapshot();
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        let (v1, pc) = (cpu.v[0x1], cpu.pc);
+
+        cpu.restore(&snapshot);
+        assert_eq!(0x200, cpu.pc);
+        assert_eq!(0, cpu.v[0x0]);
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(v1, cpu.v[0x1]);
+        assert_eq!(pc, cpu.pc);
+    }
+
+    #[test]
+    fn restore_keeps_protection() {
+        uses!(mut cpu);
+        cpu.memory.store(0x200, &[0xA0, 0x50, 0xF0, 0x55]);
+        let snapshot = cpu.snapshot();
+        cpu.memory
+            .set_protection(RegionKind::Font, Protection::Fault);
+        cpu.restore(&snapshot);
+
+        assert_eq!(Protection::Fault, cpu.memory.protection(RegionKind::Font));
+        cpu.tick();
+        cpu.tick();
+        assert!(matches!(
+            cpu.fault(),
+            Some(CpuError::Memory(MemoryError::Protected(_)))
+        ));
+    }
+
+    #[test]
+    fn timers_count_down_to_zero() {
+        uses!(mut cpu);
+        cpu.dt = 1;
+        cpu.st = 2;
+        cpu.tick_timers();
+        assert_eq!(0, cpu.dt);
+        assert_eq!(true, cpu.is_sound_playing());
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(0, cpu.dt);
+        assert_eq!(false, cpu.is_sound_playing());
+    }
 }
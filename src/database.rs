@@ -0,0 +1,274 @@
+//! Recognises ROMs by their SHA-1 hash and looks up how to run them.
+//!
+//! A [`Database`] reads the `programs.json` file of the community
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database), which lists
+//! known ROMs by hash along with the platforms they were written for, their
+//! speed, keys and colours. A subset covering a few well-known games is built
+//! in; the full file can be loaded on top of it.
+
+use crate::gamepad::{Button, ButtonMap};
+use crate::keypad::ChipKey;
+use crate::quirks::Quirks;
+use crate::screenshot::Palette;
+use crate::variant::Variant;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::{fmt, fs};
+
+const BUILTIN_PROGRAMS: &str = include_str!("../database/programs.json");
+
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    // By lower case SHA-1 hash.
+    roms: HashMap<String, RomInfo>,
+}
+
+/// What the database knows about one ROM, in chipper's terms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub file: String,
+    /// The first of the ROM's platforms chipper can run, if any.
+    pub variant: Option<Variant>,
+    /// The quirks of that platform, with any the ROM needs changed.
+    pub quirks: Quirks,
+    /// Instructions per frame.
+    pub tickrate: Option<u32>,
+    pub buttons: Option<ButtonMap>,
+    pub palette: Option<Palette>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DatabaseError {
+    Syntax(String),
+    Io(String),
+}
+
+#[derive(Deserialize)]
+struct RawProgram {
+    title: String,
+    roms: BTreeMap<String, RawRom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawRom {
+    file: String,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: BTreeMap<String, BTreeMap<String, bool>>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    colors: Option<RawColors>,
+}
+
+#[derive(Deserialize)]
+struct RawColors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// The lower case hex SHA-1 hash the database knows `rom` by.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            roms: HashMap::new(),
+        }
+    }
+
+    /// The programs shipped with chipper.
+    pub fn builtin() -> Self {
+        Database::parse(BUILTIN_PROGRAMS).expect("built-in database is valid")
+    }
+
+    /// Reads a `programs.json` file. Fields chipper has no use for are
+    /// skipped.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let programs: Vec<RawProgram> =
+            serde_json::from_str(json).map_err(|e| DatabaseError::Syntax(e.to_string()))?;
+        let mut roms = HashMap::new();
+        for program in programs {
+            for (hash, rom) in program.roms {
+                roms.insert(hash.to_ascii_lowercase(), rom.resolve(&program.title));
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    /// The built-in programs, with those of the `programs.json` at `path`
+    /// on top if one is given.
+    pub fn builtin_with<P: AsRef<Path>>(path: Option<P>) -> Result<Self, DatabaseError> {
+        let mut database = Database::builtin();
+        if let Some(path) = path {
+            let json = fs::read_to_string(path).map_err(|e| DatabaseError::Io(e.to_string()))?;
+            database.extend(Database::parse(&json)?);
+        }
+        Ok(database)
+    }
+
+    /// Adds the ROMs in `other`, replacing entries for the same ROMs.
+    pub fn extend(&mut self, other: Database) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    /// Looks up a ROM by the lower or upper case hex of its SHA-1 hash.
+    pub fn get(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(&hash.to_ascii_lowercase())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&rom_hash(rom))
+    }
+}
+
+impl RawRom {
+    fn resolve(self, title: &str) -> RomInfo {
+        // Platforms are listed best first, then the ones the ROM only runs
+        // on with some of their quirks changed.
+        let platforms = self.platforms.iter().map(|id| (id, None));
+        let quirky = self.quirky_platforms.iter().map(|(id, q)| (id, Some(q)));
+        let platform = platforms
+            .chain(quirky)
+            .find_map(|(id, changed)| Some((Variant::from_platform(id)?, changed)));
+
+        let (variant, quirks) = match platform {
+            Some((variant, changed)) => {
+                let mut quirks = Quirks::for_variant(variant);
+                for (quirk, &on) in changed.into_iter().flatten() {
+                    match quirk.as_str() {
+                        "vblank" => quirks.display_wait = on,
+                        "wrap" => quirks.wrap_sprites = on,
+                        _ => {}
+                    }
+                }
+                (Some(variant), quirks)
+            }
+            None => (None, Quirks::default()),
+        };
+
+        let mut buttons = ButtonMap::new();
+        for (name, &key) in self.keys.iter() {
+            // Second player keys have no buttons.
+            if let (Some(button), Some(key)) = (Button::parse(name), ChipKey::from_byte(key)) {
+                buttons.bind(button, key);
+            }
+        }
+        let has_buttons = buttons.iter().next().is_some();
+
+        let palette = self.colors.and_then(|colors| match &colors.pixels[..] {
+            [off, on, ..] => Palette::parse(&format!("{}:{}", off, on)),
+            _ => None,
+        });
+
+        RomInfo {
+            title: title.to_string(),
+            file: self.file,
+            variant,
+            quirks,
+            tickrate: self.tickrate,
+            buttons: if has_buttons { Some(buttons) } else { None },
+            palette,
+        }
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Syntax(message) | DatabaseError::Io(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Demo",
+            "description": "Not read.",
+            "roms": {
+                "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+                    "file": "demo.ch8",
+                    "platforms": ["megachip8", "xochip", "modernChip8"],
+                    "tickrate": 100,
+                    "keys": {"up": 5, "a": 10, "player2Up": 12},
+                    "colors": {"pixels": ["#102030", "#ffcc00"], "buzzer": "#666666"}
+                },
+                "0000000000000000000000000000000000000000": {
+                    "file": "demo (vip).ch8",
+                    "platforms": [],
+                    "quirkyPlatforms": {"originalChip8": {"vblank": false, "shift": true}}
+                }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn rom_hash_is_sha1() {
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", rom_hash(b"abc"));
+    }
+
+    #[test]
+    fn lookup_resolves_platform_keys_and_colours() {
+        let database = Database::parse(PROGRAMS).unwrap();
+        let info = database.lookup(b"abc").unwrap();
+        assert_eq!("Demo", info.title);
+        assert_eq!(Some(Variant::XoChip), info.variant);
+        assert!(info.quirks.wrap_sprites);
+        assert_eq!(Some(100), info.tickrate);
+
+        let buttons = info.buttons.as_ref().unwrap();
+        assert_eq!(
+            vec![(Button::Up, ChipKey::CK_5), (Button::A, ChipKey::CK_A)],
+            buttons.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(Palette::parse("102030:ffcc00"), info.palette);
+    }
+
+    #[test]
+    fn quirky_platforms_change_quirks() {
+        let database = Database::parse(PROGRAMS).unwrap();
+        let info = database.get(&"0".repeat(40)).unwrap();
+        assert_eq!(Some(Variant::OriginalChip8), info.variant);
+        assert!(!info.quirks.display_wait);
+        assert_eq!(None, info.buttons);
+        assert_eq!(None, info.palette);
+    }
+
+    #[test]
+    fn builtin_database_knows_tetris() {
+        let database = Database::builtin();
+        let tetris = database
+            .get("5f518084744bf3cb8733f6e5454dfd1634320563")
+            .unwrap();
+        assert_eq!("Tetris", tetris.title);
+        assert_eq!(Some(Variant::SuperChip), tetris.variant);
+        assert_eq!(
+            Some(ChipKey::CK_4),
+            tetris.buttons.as_ref().and_then(|b| b.get(Button::A))
+        );
+        assert!(database.lookup(b"abc").is_none());
+    }
+}
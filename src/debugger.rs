@@ -0,0 +1,242 @@
+use crate::cpu::{Cpu, CpuError};
+use crate::keypad::Keypad;
+use crate::memory::{SelfModification, Violation};
+use crate::rewind::History;
+use std::collections::{BTreeMap, BTreeSet};
+
+const HISTORY_SNAPSHOTS: usize = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Fault(CpuError),
+    /// The instruction at the address wrote over already executed code.
+    SelfModification(usize, SelfModification),
+    Finished,
+}
+
+/// Runs a `Cpu` one instruction or frame at a time, with breakpoints and the
+/// ability to step backwards.
+pub struct Debugger {
+    cpu: Cpu,
+    history: History,
+    breakpoints: BTreeSet<usize>,
+    instructions_per_frame: u32,
+    violations: Vec<(usize, Violation)>,
+    break_on_self_modification: bool,
+    last_self_modification: Option<(usize, SelfModification)>,
+    self_modifications: BTreeMap<(usize, SelfModification), usize>,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu, instructions_per_frame: u32) -> Self {
+        let instructions_per_frame = instructions_per_frame.max(1);
+        Debugger {
+            cpu,
+            history: History::new(HISTORY_SNAPSHOTS, instructions_per_frame as u64),
+            breakpoints: BTreeSet::new(),
+            instructions_per_frame,
+            violations: Vec::new(),
+            break_on_self_modification: false,
+            last_self_modification: None,
+            self_modifications: BTreeMap::new(),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Gives access to the `Cpu` for changes that should not be recorded,
+    /// such as memory protection settings.
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Takes the logged writes into protected memory since the last call,
+    /// each with the address of the instruction that made it.
+    pub fn take_violations(&mut self) -> Vec<(usize, Violation)> {
+        std::mem::take(&mut self.violations)
+    }
+
+    /// Makes `run_frames` stop after any instruction that writes over code
+    /// that has already run.
+    pub fn set_break_on_self_modification(&mut self, enabled: bool) {
+        self.break_on_self_modification = enabled;
+    }
+
+    pub fn breaks_on_self_modification(&self) -> bool {
+        self.break_on_self_modification
+    }
+
+    /// Every write over executed code seen so far, as the address of the
+    /// writing instruction, the write and how many times it happened.
+    pub fn self_modifications(
+        &self,
+    ) -> impl Iterator<Item = (usize, SelfModification, usize)> + '_ {
+        self.self_modifications
+            .iter()
+            .map(|(&(pc, write), &count)| (pc, write, count))
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn set_keypad(&mut self, keypad: Keypad) {
+        self.history.set_keypad(&mut self.cpu, keypad);
+    }
+
+    /// Executes one instruction, ticking the timers whenever a frame's worth
+    /// of instructions has run.
+    pub fn step(&mut self) {
+        let pc = self.cpu.pc();
+        self.history.tick(&mut self.cpu);
+        if let Some(violation) = self.cpu.take_violation() {
+            self.violations.push((pc, violation));
+        }
+        self.last_self_modification = self.cpu.take_self_modification().map(|write| (pc, write));
+        if let Some(key) = self.last_self_modification {
+            *self.self_modifications.entry(key).or_insert(0) += 1;
+        }
+        if self.history.ticks_in_frame() >= self.instructions_per_frame as usize {
+            self.history.tick_timers(&mut self.cpu);
+        }
+    }
+
+    pub fn step_back(&mut self) -> bool {
+        self.history.step_back(&mut self.cpu)
+    }
+
+    pub fn rewind_frames(&mut self, frames: usize) -> bool {
+        self.history.rewind_frames(&mut self.cpu, frames)
+    }
+
+    /// Runs until `frames` frames have finished, execution reaches a
+    /// breakpoint, the `Cpu` faults or, if enabled, the program modifies
+    /// its own code. The instruction at the current address
+    /// always runs, so continuing from a breakpoint makes progress.
+    pub fn run_frames(&mut self, frames: usize) -> StopReason {
+        let mut first = true;
+        for _ in 0..frames {
+            loop {
+                let pc = self.cpu.pc();
+                if !first && self.breakpoints.contains(&pc) {
+                    return StopReason::Breakpoint(pc);
+                }
+                first = false;
+                self.step();
+                if let Some(e) = self.cpu.fault() {
+                    return StopReason::Fault(e);
+                }
+                if let Some((pc, write)) = self.last_self_modification {
+                    if self.break_on_self_modification {
+                        return StopReason::SelfModification(pc, write);
+                    }
+                }
+                if self.history.ticks_in_frame() == 0 {
+                    break;
+                }
+            }
+        }
+        StopReason::Finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{RegionKind, StackError};
+    use pretty_assertions::assert_eq;
+
+    fn debugger(rom: &[u8]) -> Debugger {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(rom);
+        Debugger::new(cpu, 4)
+    }
+
+    #[test]
+    fn run_stops_at_breakpoint() {
+        let mut dbg = debugger(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        dbg.add_breakpoint(0x204);
+        assert_eq!(StopReason::Breakpoint(0x204), dbg.run_frames(10));
+        assert_eq!(StopReason::Breakpoint(0x204), dbg.run_frames(10));
+        assert_eq!(3, dbg.cpu().registers().v[0x0]);
+    }
+
+    #[test]
+    fn run_stops_on_fault() {
+        let mut dbg = debugger(&[0x22, 0x04, 0x00, 0xE0, 0x00, 0xEE, 0x00, 0xEE]);
+        let reason = dbg.run_frames(1);
+        assert_eq!(
+            StopReason::Fault(CpuError::Stack(StackError::Underflow)),
+            reason
+        );
+        assert!(dbg.step_back());
+        assert_eq!(None, dbg.cpu().fault());
+    }
+
+    #[test]
+    fn step_logs_protected_writes() {
+        let mut dbg = debugger(&[0xA0, 0x00, 0xF0, 0x55]);
+        dbg.step();
+        dbg.step();
+        let violations = dbg.take_violations();
+        assert_eq!(1, violations.len());
+        assert_eq!(0x202, violations[0].0);
+        assert_eq!(RegionKind::Interpreter, violations[0].1.region);
+        assert!(dbg.take_violations().is_empty());
+    }
+
+    #[test]
+    fn run_stops_on_self_modification() {
+        // Bumps the operand of its own ADD, then jumps back to run it again.
+        let mut dbg = debugger(&[
+            0x72, 0x01, 0xA2, 0x01, 0xF0, 0x65, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00,
+        ]);
+        assert_eq!(StopReason::Finished, dbg.run_frames(2));
+        dbg.set_break_on_self_modification(true);
+        let write = SelfModification {
+            address: 0x201,
+            len: 1,
+        };
+        assert_eq!(
+            StopReason::SelfModification(0x208, write),
+            dbg.run_frames(10)
+        );
+        assert_eq!(
+            vec![(0x208, write, 2)],
+            dbg.self_modifications().collect::<Vec<_>>()
+        );
+        assert_eq!(3, dbg.cpu().registers().v[0x2]);
+    }
+
+    #[test]
+    fn frames_tick_timers() {
+        let mut dbg = debugger(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+        assert_eq!(StopReason::Finished, dbg.run_frames(3));
+        assert_eq!(2, dbg.cpu().registers().dt);
+        assert!(dbg.rewind_frames(1));
+        assert_eq!(3, dbg.cpu().registers().dt);
+    }
+
+    #[test]
+    fn step_back_across_frame() {
+        let mut dbg = debugger(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+        for _ in 0..4 {
+            dbg.step();
+        }
+        assert_eq!(4, dbg.cpu().registers().dt);
+        assert!(dbg.step_back());
+        assert_eq!(5, dbg.cpu().registers().dt);
+        assert_eq!(0x204, dbg.cpu().pc());
+    }
+}
@@ -0,0 +1,396 @@
+//! Maps gamepad buttons onto the hex keypad.
+//!
+//! Frontends report presses of abstract [`Button`]s, whatever the controller,
+//! and a [`GamepadInput`] keeps a `Keypad` in step using a [`ButtonMap`].
+//! Since every game picks its own keys, button maps come from [`Profile`]s
+//! matched by ROM name. Profiles for some well-known games are built in, and
+//! more can be read from TOML:
+//!
+//! ```toml
+//! [[profile]]
+//! name = "Pong"
+//! roms = ["pong", "pong2"]
+//! buttons = { up = "1", down = "4" }
+//! ```
+
+use crate::keypad::{ChipKey, Keypad};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+
+const BUILTIN_PROFILES: &str = include_str!("../profiles/gamepad.toml");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    X,
+    Y,
+    Start,
+    Select,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ButtonMap {
+    bindings: BTreeMap<Button, ChipKey>,
+}
+
+/// A button map for the ROMs named in `roms`, which are file names without
+/// their extension and match ignoring case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub roms: Vec<String>,
+    pub buttons: ButtonMap,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+}
+
+/// Follows which buttons are held and updates a `Keypad` to match.
+pub struct GamepadInput {
+    buttons: ButtonMap,
+    held: BTreeSet<Button>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfiles {
+    #[serde(default, rename = "profile")]
+    profiles: Vec<RawProfile>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    name: String,
+    #[serde(default)]
+    roms: Vec<String>,
+    #[serde(default)]
+    buttons: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProfileError {
+    UnknownButton(String),
+    UnknownChipKey(String),
+    Syntax(String),
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::Start,
+        Button::Select,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Button::ALL
+            .iter()
+            .copied()
+            .find(|button| button.to_string().eq_ignore_ascii_case(name))
+    }
+}
+
+impl ButtonMap {
+    pub fn new() -> Self {
+        ButtonMap {
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    /// Binds `button` to `key`, replacing whatever `button` was bound to.
+    pub fn bind(&mut self, button: Button, key: ChipKey) {
+        self.bindings.insert(button, key);
+    }
+
+    pub fn unbind(&mut self, button: Button) {
+        self.bindings.remove(&button);
+    }
+
+    pub fn get(&self, button: Button) -> Option<ChipKey> {
+        self.bindings.get(&button).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Button, ChipKey)> + '_ {
+        self.bindings.iter().map(|(&button, &key)| (button, key))
+    }
+}
+
+/// The usual layout: the D-pad on 2, 8, 4 and 6 around 5 on A.
+impl Default for ButtonMap {
+    fn default() -> Self {
+        let mut buttons = ButtonMap::new();
+        buttons.bind(Button::Up, ChipKey::CK_2);
+        buttons.bind(Button::Down, ChipKey::CK_8);
+        buttons.bind(Button::Left, ChipKey::CK_4);
+        buttons.bind(Button::Right, ChipKey::CK_6);
+        buttons.bind(Button::A, ChipKey::CK_5);
+        buttons
+    }
+}
+
+impl Profile {
+    /// Whether the profile is for the ROM at `path`.
+    pub fn matches<P: AsRef<Path>>(&self, path: P) -> bool {
+        let stem = match path.as_ref().file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem,
+            None => return false,
+        };
+        self.roms.iter().any(|rom| rom.eq_ignore_ascii_case(stem))
+    }
+
+    fn from_raw(raw: RawProfile) -> Result<Self, ProfileError> {
+        let mut buttons = ButtonMap::new();
+        for (button, key) in raw.buttons {
+            let button = Button::parse(&button).ok_or(ProfileError::UnknownButton(button))?;
+            let key = u8::from_str_radix(&key, 16)
+                .ok()
+                .and_then(ChipKey::from_byte)
+                .ok_or(ProfileError::UnknownChipKey(key))?;
+            buttons.bind(button, key);
+        }
+        Ok(Profile {
+            name: raw.name,
+            roms: raw.roms,
+            buttons,
+        })
+    }
+
+    fn to_raw(&self) -> RawProfile {
+        RawProfile {
+            name: self.name.clone(),
+            roms: self.roms.clone(),
+            buttons: self
+                .buttons
+                .iter()
+                .map(|(button, key)| (button.to_string(), format!("{:X}", key as u8)))
+                .collect(),
+        }
+    }
+}
+
+impl Profiles {
+    pub fn new() -> Self {
+        Profiles {
+            profiles: Vec::new(),
+        }
+    }
+
+    /// The profiles shipped with chipper.
+    pub fn builtin() -> Self {
+        Profiles::parse(BUILTIN_PROFILES).expect("built-in profiles are valid")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        let raw: RawProfiles =
+            toml::from_str(text).map_err(|e| ProfileError::Syntax(e.message().to_string()))?;
+        let profiles = raw
+            .profiles
+            .into_iter()
+            .map(Profile::from_raw)
+            .collect::<Result<_, _>>()?;
+        Ok(Profiles { profiles })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let raw = RawProfiles {
+            profiles: self.profiles.iter().map(Profile::to_raw).collect(),
+        };
+        toml::to_string(&raw).expect("profiles are plain tables")
+    }
+
+    /// Adds `profile`, taking precedence over the ones already added.
+    pub fn add(&mut self, profile: Profile) {
+        self.profiles.insert(0, profile);
+    }
+
+    /// Adds every profile in `other`, taking precedence over these.
+    pub fn extend(&mut self, other: Profiles) {
+        self.profiles.splice(0..0, other.profiles);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.iter()
+    }
+
+    /// The profile called `name`, ignoring case.
+    pub fn named(&self, name: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn for_rom<P: AsRef<Path>>(&self, path: P) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.matches(&path))
+    }
+
+    /// The button map for the ROM at `path`, or the default one if no
+    /// profile matches.
+    pub fn buttons_for<P: AsRef<Path>>(&self, path: P) -> ButtonMap {
+        self.for_rom(path)
+            .map_or_else(ButtonMap::default, |profile| profile.buttons.clone())
+    }
+}
+
+impl GamepadInput {
+    pub fn new(buttons: ButtonMap) -> Self {
+        GamepadInput {
+            buttons,
+            held: BTreeSet::new(),
+        }
+    }
+
+    pub fn buttons(&self) -> &ButtonMap {
+        &self.buttons
+    }
+
+    /// Notes `button` going down, returning whether it is bound.
+    pub fn button_down(&mut self, button: Button) -> bool {
+        self.held.insert(button);
+        self.buttons.get(button).is_some()
+    }
+
+    pub fn button_up(&mut self, button: Button) {
+        self.held.remove(&button);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Holds the keypad keys bound to held buttons and releases the other
+    /// bound keys. Keys without a button are left alone, so a keyboard can
+    /// be applied to the same keypad first.
+    pub fn apply(&self, keypad: &mut Keypad) {
+        for (_, key) in self.buttons.iter() {
+            keypad.release_key(key);
+        }
+        for &button in self.held.iter() {
+            if let Some(key) = self.buttons.get(button) {
+                keypad.press(key);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+            Button::Start => "start",
+            Button::Select => "select",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::UnknownButton(name) => write!(f, "unknown button: {}", name),
+            ProfileError::UnknownChipKey(name) => write!(f, "not a hex key: {}", name),
+            ProfileError::Syntax(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn builtin_profiles_match_by_rom_name() {
+        let profiles = Profiles::builtin();
+        let pong = profiles.for_rom("roms/PONG.ch8").unwrap();
+        assert_eq!("Pong", pong.name);
+        assert_eq!(Some(ChipKey::CK_1), pong.buttons.get(Button::Up));
+        assert!(profiles.for_rom("roms/unknown.ch8").is_none());
+        assert_eq!(Some(pong), profiles.named("pong"));
+        assert_eq!(
+            Some(ChipKey::CK_5),
+            profiles.buttons_for("unknown.ch8").get(Button::A)
+        );
+    }
+
+    #[test]
+    fn profiles_round_trip_through_toml() {
+        let profiles = Profiles::builtin();
+        assert_eq!(profiles, Profiles::parse(&profiles.to_toml()).unwrap());
+    }
+
+    #[test]
+    fn added_profiles_take_precedence() {
+        let mut profiles = Profiles::builtin();
+        profiles.extend(
+            Profiles::parse(
+                r#"
+                [[profile]]
+                name = "Two player Pong"
+                roms = ["PONG"]
+                buttons = { up = "C", down = "D" }
+                "#,
+            )
+            .unwrap(),
+        );
+        let pong = profiles.for_rom("pong.ch8").unwrap();
+        assert_eq!(Some(ChipKey::CK_C), pong.buttons.get(Button::Up));
+    }
+
+    #[test]
+    fn profiles_report_bad_names() {
+        assert_eq!(
+            Err(ProfileError::UnknownButton("turbo".to_string())),
+            Profiles::parse("[[profile]]\nname = \"x\"\nbuttons = { turbo = \"1\" }")
+        );
+        assert_eq!(
+            Err(ProfileError::UnknownChipKey("10".to_string())),
+            Profiles::parse("[[profile]]\nname = \"x\"\nbuttons = { a = \"10\" }")
+        );
+    }
+
+    #[test]
+    fn apply_leaves_unbound_keys_alone() {
+        let mut input = GamepadInput::new(ButtonMap::default());
+        let mut keypad = Keypad::new();
+        keypad.press(ChipKey::CK_F);
+        keypad.press(ChipKey::CK_2);
+
+        assert!(input.button_down(Button::Left));
+        assert!(input.button_down(Button::A));
+        assert!(!input.button_down(Button::Start));
+        input.apply(&mut keypad);
+        assert!(keypad.is_pressed(ChipKey::CK_4));
+        assert!(keypad.is_pressed(ChipKey::CK_5));
+        assert!(keypad.is_pressed(ChipKey::CK_F));
+        assert!(!keypad.is_pressed(ChipKey::CK_2));
+
+        input.button_up(Button::A);
+        input.apply(&mut keypad);
+        assert!(!keypad.is_pressed(ChipKey::CK_5));
+    }
+}
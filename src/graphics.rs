@@ -2,44 +2,124 @@ pub const GRAPHICS_WIDTH: usize = 64;
 pub const GRAPHICS_HEIGHT: usize = 32;
 pub const GRAPHICS_VBUFFER: usize = (GRAPHICS_WIDTH * GRAPHICS_HEIGHT) / 8;
 
-#[derive(Default)]
+const ROW_BYTES: usize = GRAPHICS_WIDTH / 8;
+
+/// Monochrome framebuffer, packed one bit per pixel in row-major order with
+/// the most significant bit of each byte being the leftmost pixel.
+#[derive(Clone)]
 pub struct Graphics {
-    vbuffer: Vec<u8>,
+    vbuffer: [u8; GRAPHICS_VBUFFER],
+}
+
+impl Default for Graphics {
+    fn default() -> Self {
+        Graphics::new()
+    }
 }
 
 impl Graphics {
     pub fn new() -> Self {
         Graphics {
-            vbuffer: vec![0; GRAPHICS_VBUFFER],
+            vbuffer: [0; GRAPHICS_VBUFFER],
         }
     }
 
     pub fn clear(&mut self) {
-        self.vbuffer = vec![0; GRAPHICS_VBUFFER]
+        self.vbuffer.fill(0);
     }
 
+    /// Copies `len` packed bytes starting at the byte containing pixel
+    /// (`col`, `row`). Prefer `slice`, which does not allocate.
+    #[cfg(feature = "std")]
     pub fn read_buffer(&self, row: usize, col: usize, len: usize) -> Vec<u8> {
+        self.slice(row, col, len).to_vec()
+    }
+
+    /// Borrows `len` packed bytes starting at the byte containing pixel
+    /// (`col`, `row`).
+    pub fn slice(&self, row: usize, col: usize, len: usize) -> &[u8] {
         let index = Self::flatten_index(row, col);
-        self.vbuffer[index..index + len].to_vec()
+        &self.vbuffer[index..index + len]
+    }
+
+    pub fn is_pixel_set(&self, x: usize, y: usize) -> bool {
+        let index = Self::flatten_index(y, x);
+        self.vbuffer[index] & Self::pixel_mask(x) != 0
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, lit: bool) {
+        let index = Self::flatten_index(y, x);
+        if lit {
+            self.vbuffer[index] |= Self::pixel_mask(x);
+        } else {
+            self.vbuffer[index] &= !Self::pixel_mask(x);
+        }
+    }
+
+    /// XORs `sprite` onto the screen, one byte per row, with its top left
+    /// corner at (`x`, `y`) taken modulo the screen size. Pixels falling off
+    /// the right or bottom edge are clipped. Returns whether any lit pixel
+    /// was turned off.
+    pub fn draw_with_collision(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.draw(x, y, sprite, false)
+    }
+
+    /// Like `draw_with_collision`, but pixels falling off an edge wrap
+    /// around to the opposite one.
+    pub fn draw_wrapping(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.draw(x, y, sprite, true)
     }
 
-    pub fn draw_with_collision(&mut self, row: usize, col: usize, sprite: &[u8]) -> bool {
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let (x, y) = (x % GRAPHICS_WIDTH, y % GRAPHICS_HEIGHT);
         let mut collision = false;
-        let index = Self::flatten_index(row, col);
-        for (i, byte) in self.vbuffer[index..index + sprite.len()]
-            .iter_mut()
-            .enumerate()
-        {
-            *byte ^= sprite[i];
-            if *byte != sprite[i] {
-                collision = true;
+        for (i, line) in sprite.iter().enumerate() {
+            let mut row = y + i;
+            if row >= GRAPHICS_HEIGHT {
+                if !wrap {
+                    break;
+                }
+                row %= GRAPHICS_HEIGHT;
+            }
+            for bit in 0..8 {
+                let mut col = x + bit;
+                if col >= GRAPHICS_WIDTH {
+                    if !wrap {
+                        break;
+                    }
+                    col %= GRAPHICS_WIDTH;
+                }
+                if line & (0x80 >> bit) != 0 {
+                    collision |= self.flip_pixel(col, row);
+                }
             }
         }
         collision
     }
 
-    fn flatten_index(x: usize, y: usize) -> usize {
-        y * GRAPHICS_WIDTH + x
+    /// The whole packed framebuffer.
+    pub fn bytes(&self) -> &[u8] {
+        &self.vbuffer
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.vbuffer
+    }
+
+    fn flip_pixel(&mut self, x: usize, y: usize) -> bool {
+        let index = Self::flatten_index(y, x);
+        let mask = Self::pixel_mask(x);
+        let was_set = self.vbuffer[index] & mask != 0;
+        self.vbuffer[index] ^= mask;
+        was_set
+    }
+
+    fn flatten_index(row: usize, col: usize) -> usize {
+        row * ROW_BYTES + col / 8
+    }
+
+    fn pixel_mask(col: usize) -> u8 {
+        0x80 >> (col % 8)
     }
 }
 
@@ -52,8 +132,9 @@ mod tests {
     fn draw_with_collision_no_overlap() {
         let mut gfx = Graphics::new();
         let bytes = &[0x9A, 0x3C];
-        let collision = gfx.draw_with_collision(2, 2, bytes);
-        assert_eq!(bytes, &gfx.vbuffer[130..132]);
+        let collision = gfx.draw_with_collision(8, 2, bytes);
+        assert_eq!(bytes[0], gfx.vbuffer[17]);
+        assert_eq!(bytes[1], gfx.vbuffer[25]);
         assert_eq!(false, collision);
     }
 
@@ -61,9 +142,101 @@ mod tests {
     fn draw_with_collision_overlap() {
         let mut gfx = Graphics::new();
         let bytes = &[0x9A, 0x3C];
-        gfx.draw_with_collision(2, 2, &[bytes[0]]);
-        let collision = gfx.draw_with_collision(2, 2, &[bytes[1]]);
-        assert_eq!(0xA6, gfx.vbuffer[130]);
+        gfx.draw_with_collision(8, 2, &[bytes[0]]);
+        let collision = gfx.draw_with_collision(8, 2, &[bytes[1]]);
+        assert_eq!(0xA6, gfx.vbuffer[17]);
         assert_eq!(true, collision);
     }
+
+    #[test]
+    fn draw_with_collision_unaligned() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(4, 0, &[0xFF]);
+        assert_eq!(vec![0x0F, 0xF0], gfx.read_buffer(0, 0, 2));
+        assert_eq!(true, gfx.is_pixel_set(4, 0));
+        assert_eq!(false, gfx.is_pixel_set(3, 0));
+    }
+
+    #[test]
+    fn clear_keeps_buffer() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(0, 0, &[0xFF]);
+        let buffer = gfx.bytes().as_ptr();
+        gfx.clear();
+        assert_eq!(buffer, gfx.bytes().as_ptr());
+        assert!(gfx.bytes().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn draw_with_collision_clips_edges() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(60, 31, &[0xFF, 0xFF]);
+        assert_eq!(vec![0x0F], gfx.read_buffer(31, 56, 1));
+        assert_eq!(false, gfx.is_pixel_set(0, 0));
+    }
+
+    type Pixel = (usize, usize);
+
+    fn lit_pixels(gfx: &Graphics) -> Vec<Pixel> {
+        let mut lit = Vec::new();
+        for y in 0..GRAPHICS_HEIGHT {
+            for x in 0..GRAPHICS_WIDTH {
+                if gfx.is_pixel_set(x, y) {
+                    lit.push((x, y));
+                }
+            }
+        }
+        lit
+    }
+
+    // A 2x2 block in the sprite's top left corner.
+    const BLOCK: &[u8] = &[0xC0, 0xC0];
+
+    #[test]
+    fn origin_wraps_around_the_screen() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(64 + 3, 32 + 5, BLOCK);
+        assert_eq!(vec![(3, 5), (4, 5), (3, 6), (4, 6)], lit_pixels(&gfx));
+        gfx.clear();
+        gfx.draw_with_collision(255, 255, &[0x80]);
+        assert_eq!(vec![(63, 31)], lit_pixels(&gfx));
+    }
+
+    #[test]
+    fn clipping_at_each_edge_and_corner() {
+        let cases: &[(Pixel, &[Pixel])] = &[
+            ((63, 10), &[(63, 10), (63, 11)]),
+            ((10, 31), &[(10, 31), (11, 31)]),
+            ((63, 31), &[(63, 31)]),
+            ((0, 0), &[(0, 0), (1, 0), (0, 1), (1, 1)]),
+        ];
+        for &((x, y), expected) in cases {
+            let mut gfx = Graphics::new();
+            gfx.draw_with_collision(x, y, BLOCK);
+            assert_eq!(expected.to_vec(), lit_pixels(&gfx), "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn wrapping_at_each_edge_and_corner() {
+        let cases: &[(Pixel, &[Pixel])] = &[
+            ((63, 10), &[(0, 10), (63, 10), (0, 11), (63, 11)]),
+            ((10, 31), &[(10, 0), (11, 0), (10, 31), (11, 31)]),
+            ((63, 31), &[(0, 0), (63, 0), (0, 31), (63, 31)]),
+            ((63 + 64, 31 + 32), &[(0, 0), (63, 0), (0, 31), (63, 31)]),
+        ];
+        for &((x, y), expected) in cases {
+            let mut gfx = Graphics::new();
+            gfx.draw_wrapping(x, y, BLOCK);
+            assert_eq!(expected.to_vec(), lit_pixels(&gfx), "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn wrapped_pixels_collide() {
+        let mut gfx = Graphics::new();
+        gfx.draw_with_collision(0, 0, &[0x80]);
+        assert_eq!(true, gfx.draw_wrapping(63, 31, BLOCK));
+        assert_eq!(false, gfx.is_pixel_set(0, 0));
+    }
 }
@@ -0,0 +1,312 @@
+//! Runs ROMs headlessly and checks what ends up on the screen.
+//!
+//! A [`Harness`] runs a ROM for a number of frames on the `Cpu` its
+//! [`CpuBuilder`] describes, holding keys as its script says, and returns the
+//! final screen. Screens are compared against reference images read with
+//! [`screenshot::load`], either plain PBM images, which are easy to review in
+//! a diff, or PNG images, or against a hash of the framebuffer.
+
+use crate::cpu::{CpuBuilder, CpuError};
+use crate::graphics::{Graphics, GRAPHICS_HEIGHT, GRAPHICS_WIDTH};
+use crate::keypad::{ChipKey, Keypad};
+use crate::memory::{CHIP8_RAM, PROGRAM_START};
+use crate::quirks::Quirks;
+use crate::recording::Recording;
+pub use crate::runtime::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use crate::runtime::{Host, Runtime, FRAMES_PER_SECOND};
+use crate::screenshot;
+use crate::timing::Timing;
+use std::fmt;
+
+pub struct Harness {
+    frames: u32,
+    instructions_per_frame: u32,
+    timing: Timing,
+    cpu: CpuBuilder,
+    // Keypad changes by frame, in the order they were added.
+    script: Vec<(u32, Option<ChipKey>)>,
+}
+
+/// The state a ROM was left in after a run.
+pub struct Run {
+    pub graphics: Graphics,
+    pub fault: Option<CpuError>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HarnessError {
+    RomTooLarge(usize),
+}
+
+/// How a screen differs from the expected one.
+pub struct Mismatch {
+    pub pixels: usize,
+    /// The screen row by row, marking lit pixels `#`, pixels that should
+    /// have been lit `-` and pixels that should have been dark `+`.
+    pub diff: String,
+}
+
+struct Script<'a> {
+    frame: u32,
+    changes: &'a [(u32, Option<ChipKey>)],
+    recording: Option<&'a mut Recording>,
+}
+
+impl Harness {
+    pub fn new(frames: u32) -> Self {
+        Harness {
+            frames,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            timing: Timing::Fixed,
+            cpu: CpuBuilder::new(),
+            script: Vec::new(),
+        }
+    }
+
+    pub fn instructions_per_frame(mut self, instructions_per_frame: u32) -> Self {
+        self.instructions_per_frame = instructions_per_frame;
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Sets the variant, quirks and font of the `Cpu`, such as with
+    /// [`RomConfig::cpu_builder`](crate::config::RomConfig::cpu_builder).
+    pub fn cpu(mut self, cpu: CpuBuilder) -> Self {
+        self.cpu = cpu;
+        self
+    }
+
+    /// Overrides every quirk of the `Cpu`'s variant.
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.cpu = self.cpu.quirks(quirks);
+        self
+    }
+
+    /// Holds `key` from the start of `frame` until it is released or
+    /// another key is pressed.
+    pub fn press(mut self, frame: u32, key: ChipKey) -> Self {
+        self.script.push((frame, Some(key)));
+        self
+    }
+
+    /// Releases every key from the start of `frame`.
+    pub fn release(mut self, frame: u32) -> Self {
+        self.script.push((frame, None));
+        self
+    }
+
+    /// Runs `rom` for the configured number of frames, stopping early if it
+    /// faults.
+    pub fn run(&self, rom: &[u8]) -> Result<Run, HarnessError> {
+        self.run_script(rom, None)
+    }
+
+    /// Runs `rom` like [`run`](Self::run), capturing every frame into
+    /// `recording`.
+    pub fn record(&self, rom: &[u8], recording: &mut Recording) -> Result<Run, HarnessError> {
+        self.run_script(rom, Some(recording))
+    }
+
+    fn run_script(
+        &self,
+        rom: &[u8],
+        recording: Option<&mut Recording>,
+    ) -> Result<Run, HarnessError> {
+        if rom.len() > CHIP8_RAM - PROGRAM_START {
+            return Err(HarnessError::RomTooLarge(rom.len()));
+        }
+        let mut cpu = self.cpu.build();
+        cpu.load_rom(rom);
+        let mut runtime = Runtime::new(cpu);
+        runtime.set_instructions_per_second(self.instructions_per_frame * FRAMES_PER_SECOND);
+        runtime.set_timing(self.timing);
+
+        let mut script = Script {
+            frame: 0,
+            changes: &self.script,
+            recording,
+        };
+        for _ in 0..self.frames {
+            runtime.step_frame(&mut script);
+            script.frame += 1;
+            if runtime.cpu().fault().is_some() {
+                break;
+            }
+        }
+        let cpu = runtime.into_cpu();
+        Ok(Run {
+            graphics: cpu.graphics().clone(),
+            fault: cpu.fault(),
+        })
+    }
+}
+
+impl Host for Script<'_> {
+    fn input(&mut self, keypad: &mut Keypad) {
+        for &(_, key) in self.changes.iter().filter(|(at, _)| *at == self.frame) {
+            match key {
+                Some(key) => {
+                    keypad.release();
+                    keypad.press(key);
+                }
+                None => keypad.release(),
+            }
+        }
+    }
+
+    fn frame(&mut self, graphics: &Graphics) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.capture(graphics);
+        }
+    }
+}
+
+impl Run {
+    /// A 64-bit FNV-1a hash of the framebuffer.
+    pub fn hash(&self) -> u64 {
+        frame_hash(&self.graphics)
+    }
+
+    pub fn to_pbm(&self) -> String {
+        screenshot::to_pbm(&self.graphics)
+    }
+
+    pub fn compare(&self, expected: &Graphics) -> Result<(), Mismatch> {
+        compare(&self.graphics, expected)
+    }
+}
+
+pub fn frame_hash(graphics: &Graphics) -> u64 {
+    graphics
+        .bytes()
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+pub fn compare(actual: &Graphics, expected: &Graphics) -> Result<(), Mismatch> {
+    if actual.bytes() == expected.bytes() {
+        return Ok(());
+    }
+    let mut pixels = 0;
+    let mut diff = String::new();
+    for y in 0..GRAPHICS_HEIGHT {
+        for x in 0..GRAPHICS_WIDTH {
+            let c = match (actual.is_pixel_set(x, y), expected.is_pixel_set(x, y)) {
+                (true, true) => '#',
+                (false, false) => '.',
+                (false, true) => '-',
+                (true, false) => '+',
+            };
+            if c == '-' || c == '+' {
+                pixels += 1;
+            }
+            diff.push(c);
+        }
+        diff.push('\n');
+    }
+    Err(Mismatch { pixels, diff })
+}
+
+impl fmt::Display for HarnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HarnessError::RomTooLarge(len) => {
+                write!(f, "{}-byte ROM is too large to fit in memory", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HarnessError {}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} pixels differ (- should be lit, + should be dark):",
+            self.pixels
+        )?;
+        write!(f, "{}", self.diff)
+    }
+}
+
+impl fmt::Debug for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+    use pretty_assertions::assert_eq;
+
+    // Waits for a key, then draws its digit at the top left.
+    const SHOW_KEY: &[u8] = &[
+        0xF0, 0x0A, // LD V0, K
+        0xF0, 0x29, // LD F, V0
+        0xD1, 0x15, // DRW V1, V1, 5
+        0x12, 0x06, // JP 0x206
+    ];
+
+    #[test]
+    fn scripted_key_is_drawn() {
+        let run = Harness::new(5)
+            .press(2, ChipKey::CK_7)
+            .run(SHOW_KEY)
+            .unwrap();
+        let mut expected = Graphics::new();
+        expected.draw_with_collision(0, 0, &[0xF0, 0x10, 0x20, 0x40, 0x40]);
+        assert!(run.compare(&expected).is_ok());
+        assert_eq!(None, run.fault);
+    }
+
+    #[test]
+    fn mismatch_marks_differing_pixels() {
+        let run = Harness::new(5)
+            .press(2, ChipKey::CK_1)
+            .run(SHOW_KEY)
+            .unwrap();
+        let mut expected = Graphics::new();
+        expected.draw_with_collision(0, 0, &[0xF0, 0x10, 0x20, 0x40, 0x40]);
+        let mismatch = run.compare(&expected).unwrap_err();
+        assert_eq!("--#-....", &mismatch.diff[..8]);
+        assert_ne!(run.hash(), frame_hash(&expected));
+    }
+
+    #[test]
+    fn runs_on_the_built_variant() {
+        // Draws the top row of 0 at the right edge.
+        let rom = [
+            0x60, 0x3E, // LD V0, 0x3E
+            0xF1, 0x29, // LD F, V1
+            0xD0, 0x11, // DRW V0, V1, 1
+            0x12, 0x06, // JP 0x206
+        ];
+        let run = Harness::new(2).run(&rom).unwrap();
+        assert!(!run.graphics.is_pixel_set(0, 0));
+        let run = Harness::new(2)
+            .cpu(CpuBuilder::new().variant(Variant::XoChip))
+            .run(&rom)
+            .unwrap();
+        assert!(run.graphics.is_pixel_set(0, 0));
+    }
+
+    #[test]
+    fn bad_roms_fault_instead_of_panicking() {
+        // HIGH, which only SCHIP runs.
+        let run = Harness::new(2).run(&[0x00, 0xFF]).unwrap();
+        assert_eq!(Some(CpuError::UnsupportedInstruction(0x00FF)), run.fault);
+
+        // LD I, 0xFFC; LD V5, [I]
+        let run = Harness::new(2).run(&[0xAF, 0xFC, 0xF5, 0x65]).unwrap();
+        assert!(run.fault.is_some());
+    }
+}
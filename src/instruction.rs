@@ -0,0 +1,419 @@
+#[cfg(feature = "std")]
+use crate::memory::{Memory, MemoryError, CHIP8_RAM};
+use core::fmt;
+#[cfg(feature = "std")]
+use core::ops::Range;
+
+/// A decoded instruction, named after the mnemonics in Cowgod's Chip-8
+/// technical reference. Super CHIP-48 and XO-CHIP instructions are decoded so
+/// they can be disassembled and analysed, even though the interpreter does
+/// not execute them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Sys(usize),
+    Jp(usize),
+    Call(usize),
+    SeByte(usize, u8),
+    SneByte(usize, u8),
+    SeReg(usize, usize),
+    LdByte(usize, u8),
+    AddByte(usize, u8),
+    LdReg(usize, usize),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
+    AddReg(usize, usize),
+    Sub(usize, usize),
+    Shr(usize, usize),
+    Subn(usize, usize),
+    Shl(usize, usize),
+    SneReg(usize, usize),
+    LdI(usize),
+    JpV0(usize),
+    Rnd(usize, u8),
+    Drw(usize, usize, usize),
+    Skp(usize),
+    Sknp(usize),
+    LdVxDt(usize),
+    LdVxK(usize),
+    LdDtVx(usize),
+    LdStVx(usize),
+    AddI(usize),
+    LdF(usize),
+    LdB(usize),
+    LdIVx(usize),
+    LdVxI(usize),
+    Scd(usize),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    DrwHigh(usize, usize),
+    LdHf(usize),
+    LdRVx(usize),
+    LdVxR(usize),
+    Scu(usize),
+    SaveRange(usize, usize),
+    LoadRange(usize, usize),
+    /// `F000`, which loads I from the word after it.
+    LdILong,
+    Plane(usize),
+    Audio,
+    Pitch(usize),
+    Unknown(u16),
+}
+
+/// Instruction sets extending CHIP-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Extension {
+    SuperChip,
+    XoChip,
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        use Instruction::*;
+        let nnn = (opcode & 0x0FFF) as usize;
+        let kk = (opcode & 0x00FF) as u8;
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let n = (opcode & 0x000F) as usize;
+
+        match (opcode >> 12, x, y, n) {
+            (0x0, 0x0, 0xC, _) => Scd(n),
+            (0x0, 0x0, 0xD, _) => Scu(n),
+            (0x0, 0x0, 0xE, 0x0) => Cls,
+            (0x0, 0x0, 0xE, 0xE) => Ret,
+            (0x0, 0x0, 0xF, 0xB) => Scr,
+            (0x0, 0x0, 0xF, 0xC) => Scl,
+            (0x0, 0x0, 0xF, 0xD) => Exit,
+            (0x0, 0x0, 0xF, 0xE) => Low,
+            (0x0, 0x0, 0xF, 0xF) => High,
+            (0x0, _, _, _) => Sys(nnn),
+            (0x1, _, _, _) => Jp(nnn),
+            (0x2, _, _, _) => Call(nnn),
+            (0x3, _, _, _) => SeByte(x, kk),
+            (0x4, _, _, _) => SneByte(x, kk),
+            (0x5, _, _, 0x0) => SeReg(x, y),
+            (0x5, _, _, 0x2) => SaveRange(x, y),
+            (0x5, _, _, 0x3) => LoadRange(x, y),
+            (0x6, _, _, _) => LdByte(x, kk),
+            (0x7, _, _, _) => AddByte(x, kk),
+            (0x8, _, _, 0x0) => LdReg(x, y),
+            (0x8, _, _, 0x1) => Or(x, y),
+            (0x8, _, _, 0x2) => And(x, y),
+            (0x8, _, _, 0x3) => Xor(x, y),
+            (0x8, _, _, 0x4) => AddReg(x, y),
+            (0x8, _, _, 0x5) => Sub(x, y),
+            (0x8, _, _, 0x6) => Shr(x, y),
+            (0x8, _, _, 0x7) => Subn(x, y),
+            (0x8, _, _, 0xE) => Shl(x, y),
+            (0x9, _, _, 0x0) => SneReg(x, y),
+            (0xA, _, _, _) => LdI(nnn),
+            (0xB, _, _, _) => JpV0(nnn),
+            (0xC, _, _, _) => Rnd(x, kk),
+            (0xD, _, _, 0x0) => DrwHigh(x, y),
+            (0xD, _, _, _) => Drw(x, y, n),
+            (0xE, _, 0x9, 0xE) => Skp(x),
+            (0xE, _, 0xA, 0x1) => Sknp(x),
+            (0xF, 0x0, 0x0, 0x0) => LdILong,
+            (0xF, _, 0x0, 0x1) => Plane(x),
+            (0xF, 0x0, 0x0, 0x2) => Audio,
+            (0xF, _, 0x0, 0x7) => LdVxDt(x),
+            (0xF, _, 0x0, 0xA) => LdVxK(x),
+            (0xF, _, 0x1, 0x5) => LdDtVx(x),
+            (0xF, _, 0x1, 0x8) => LdStVx(x),
+            (0xF, _, 0x1, 0xE) => AddI(x),
+            (0xF, _, 0x2, 0x9) => LdF(x),
+            (0xF, _, 0x3, 0x0) => LdHf(x),
+            (0xF, _, 0x3, 0x3) => LdB(x),
+            (0xF, _, 0x3, 0xA) => Pitch(x),
+            (0xF, _, 0x5, 0x5) => LdIVx(x),
+            (0xF, _, 0x6, 0x5) => LdVxI(x),
+            (0xF, _, 0x7, 0x5) => LdRVx(x),
+            (0xF, _, 0x8, 0x5) => LdVxR(x),
+            _ => Unknown(opcode),
+        }
+    }
+
+    /// Bytes the instruction takes up, counting the address after `F000`.
+    pub fn size(self) -> usize {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// The instruction set the instruction comes from, if not CHIP-8's.
+    pub fn extension(self) -> Option<Extension> {
+        use Instruction::*;
+        match self {
+            Scd(_) | Scr | Scl | Exit | Low | High | DrwHigh(..) | LdHf(_) | LdRVx(_)
+            | LdVxR(_) => Some(Extension::SuperChip),
+            Scu(_) | SaveRange(..) | LoadRange(..) | LdILong | Plane(_) | Audio | Pitch(_) => {
+                Some(Extension::XoChip)
+            }
+            _ => None,
+        }
+    }
+
+    /// The opcode with its operands as letters, e.g. `Dxyn`.
+    pub fn pattern(self) -> &'static str {
+        use Instruction::*;
+        match self {
+            Cls => "00E0",
+            Ret => "00EE",
+            Sys(_) => "0nnn",
+            Jp(_) => "1nnn",
+            Call(_) => "2nnn",
+            SeByte(..) => "3xkk",
+            SneByte(..) => "4xkk",
+            SeReg(..) => "5xy0",
+            LdByte(..) => "6xkk",
+            AddByte(..) => "7xkk",
+            LdReg(..) => "8xy0",
+            Or(..) => "8xy1",
+            And(..) => "8xy2",
+            Xor(..) => "8xy3",
+            AddReg(..) => "8xy4",
+            Sub(..) => "8xy5",
+            Shr(..) => "8xy6",
+            Subn(..) => "8xy7",
+            Shl(..) => "8xyE",
+            SneReg(..) => "9xy0",
+            LdI(_) => "Annn",
+            JpV0(_) => "Bnnn",
+            Rnd(..) => "Cxkk",
+            Drw(..) => "Dxyn",
+            Skp(_) => "Ex9E",
+            Sknp(_) => "ExA1",
+            LdVxDt(_) => "Fx07",
+            LdVxK(_) => "Fx0A",
+            LdDtVx(_) => "Fx15",
+            LdStVx(_) => "Fx18",
+            AddI(_) => "Fx1E",
+            LdF(_) => "Fx29",
+            LdB(_) => "Fx33",
+            LdIVx(_) => "Fx55",
+            LdVxI(_) => "Fx65",
+            Scd(_) => "00Cn",
+            Scr => "00FB",
+            Scl => "00FC",
+            Exit => "00FD",
+            Low => "00FE",
+            High => "00FF",
+            DrwHigh(..) => "Dxy0",
+            LdHf(_) => "Fx30",
+            LdRVx(_) => "Fx75",
+            LdVxR(_) => "Fx85",
+            Scu(_) => "00Dn",
+            SaveRange(..) => "5xy2",
+            LoadRange(..) => "5xy3",
+            LdILong => "F000",
+            Plane(_) => "Fn01",
+            Audio => "F002",
+            Pitch(_) => "Fx3A",
+            Unknown(_) => "????",
+        }
+    }
+}
+
+/// Instructions already decoded, by address, so that loops decode each
+/// instruction only once. Entries must be invalidated when memory changes.
+#[cfg(feature = "std")]
+pub struct DecodeCache {
+    entries: Box<[Option<Instruction>]>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DecodeCache {
+    fn default() -> Self {
+        DecodeCache::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache {
+            entries: vec![None; CHIP8_RAM].into_boxed_slice(),
+        }
+    }
+
+    /// Decodes the instruction at `address`, or returns it from the cache.
+    pub fn fetch(&mut self, memory: &Memory, address: usize) -> Result<Instruction, MemoryError> {
+        if let Some(instruction) = self.entries.get(address).copied().flatten() {
+            return Ok(instruction);
+        }
+        let instruction = Instruction::decode(memory.read_word(address)?);
+        self.entries[address] = Some(instruction);
+        Ok(instruction)
+    }
+
+    /// Forgets every instruction overlapping the bytes in `range`.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let start = range.start.saturating_sub(1);
+        let end = range.end.min(CHIP8_RAM);
+        for entry in self.entries[start..end].iter_mut() {
+            *entry = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.invalidate(0..CHIP8_RAM);
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            SeByte(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            SneByte(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LdByte(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            AddByte(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Rnd(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            LdF(x) => write!(f, "LD F, V{:X}", x),
+            LdB(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Scd(n) => write!(f, "SCD {}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            DrwHigh(x, y) => write!(f, "DRW V{:X}, V{:X}, 0", x, y),
+            LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Scu(n) => write!(f, "SCU {}", n),
+            SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            LdILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Extension::SuperChip => write!(f, "SCHIP"),
+            Extension::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decode_splits_operands() {
+        assert_eq!(Instruction::Drw(0xA, 0xB, 0x5), Instruction::decode(0xDAB5));
+        assert_eq!(Instruction::LdByte(0x3, 0x7F), Instruction::decode(0x637F));
+        assert_eq!(Instruction::JpV0(0x123), Instruction::decode(0xB123));
+    }
+
+    #[test]
+    fn decode_prefers_specific_0nnn_forms() {
+        assert_eq!(Instruction::Cls, Instruction::decode(0x00E0));
+        assert_eq!(Instruction::Ret, Instruction::decode(0x00EE));
+        assert_eq!(Instruction::Sys(0x123), Instruction::decode(0x0123));
+    }
+
+    #[test]
+    fn decode_unknown() {
+        assert_eq!(Instruction::Unknown(0x5121), Instruction::decode(0x5121));
+        assert_eq!(Instruction::Unknown(0xFFFF), Instruction::decode(0xFFFF));
+    }
+
+    #[test]
+    fn decode_xo_chip() {
+        assert_eq!(Instruction::Scu(4), Instruction::decode(0x00D4));
+        assert_eq!(Instruction::SaveRange(1, 3), Instruction::decode(0x5132));
+        assert_eq!(Instruction::LdILong, Instruction::decode(0xF000));
+        assert_eq!(Instruction::Plane(2), Instruction::decode(0xF201));
+        assert_eq!(Instruction::Pitch(7), Instruction::decode(0xF73A));
+        assert_eq!(4, Instruction::LdILong.size());
+        assert_eq!(
+            Some(Extension::XoChip),
+            Instruction::decode(0xF002).extension()
+        );
+        assert_eq!(
+            Some(Extension::SuperChip),
+            Instruction::decode(0x00FF).extension()
+        );
+        assert_eq!(None, Instruction::decode(0xF065).extension());
+    }
+
+    #[test]
+    fn disassemble() {
+        assert_eq!("LD V0, 0x75", Instruction::decode(0x6075).to_string());
+        assert_eq!("CALL 0x2AB", Instruction::decode(0x22AB).to_string());
+        assert_eq!("DRW V1, V2, 5", Instruction::decode(0xD125).to_string());
+        assert_eq!("LD [I], VF", Instruction::decode(0xFF55).to_string());
+        assert_eq!("DW 0xFFFF", Instruction::decode(0xFFFF).to_string());
+    }
+
+    #[test]
+    fn cache_is_invalidated_by_overlapping_writes() {
+        let mut memory = Memory::new();
+        memory.store(0x200, &[0x60, 0x01, 0x70, 0x02]);
+        let mut cache = DecodeCache::new();
+        assert_eq!(
+            Instruction::LdByte(0, 1),
+            cache.fetch(&memory, 0x200).unwrap()
+        );
+
+        memory.store(0x201, &[0x05]);
+        assert_eq!(
+            Instruction::LdByte(0, 1),
+            cache.fetch(&memory, 0x200).unwrap()
+        );
+        cache.invalidate(0x202..0x203);
+        assert_eq!(
+            Instruction::LdByte(0, 1),
+            cache.fetch(&memory, 0x200).unwrap()
+        );
+        cache.invalidate(0x201..0x202);
+        assert_eq!(
+            Instruction::LdByte(0, 5),
+            cache.fetch(&memory, 0x200).unwrap()
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! Maps host keyboard keys onto the hex keypad.
+//!
+//! A [`Keymap`] binds host keys to `ChipKey`s, any number of host keys to
+//! each. The usual layouts are built in, and a [`KeymapConfig`] file can
+//! change the layout or bindings for all ROMs or for one ROM by file name:
+//!
+//! ```toml
+//! layout = "azerty"
+//!
+//! [keys]
+//! 5 = ["z", "up"]
+//!
+//! [roms."pong.ch8".keys]
+//! 1 = ["w"]
+//! 4 = ["s"]
+//! ```
+//!
+//! Listing a keypad key replaces the host keys bound to it. A
+//! [`KeyboardInput`] then follows key presses and releases and keeps a
+//! `Keypad` in step.
+
+use crate::keypad::{ChipKey, Keypad};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Keypad keys in the order of the COSMAC VIP's hex keypad, row by row.
+pub const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HostKey {
+    /// A key typing a character, kept in lower case.
+    Char(char),
+    /// A key on the numeric keypad, by the character printed on it.
+    Numpad(char),
+    NumpadEnter,
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// The built-in layouts. The first three put the keypad on the four by four
+/// block under the digit keys of their keyboards, typed without Shift.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Dvorak,
+    /// The numeric keypad's digits map to themselves, and `/`, `*`, `-`,
+    /// `+`, Enter and `.` to A to F.
+    Numpad,
+    /// Typing the hex digit printed on each key of the VIP's keypad.
+    Hex,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: BTreeMap<HostKey, ChipKey>,
+}
+
+/// Follows which host keys are held and updates a `Keypad` to match.
+pub struct KeyboardInput {
+    keymap: Keymap,
+    held: BTreeSet<HostKey>,
+}
+
+/// A keymap file: a layout and bindings for every ROM, with overrides by
+/// ROM file name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeymapConfig {
+    default: Section,
+    roms: BTreeMap<String, Section>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Section {
+    layout: Option<Layout>,
+    keys: Vec<(ChipKey, Vec<HostKey>)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    layout: Option<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    roms: BTreeMap<String, RawSection>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSection {
+    layout: Option<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeymapError {
+    /// A row layout was not 16 keys long.
+    WrongLength(usize),
+    UnknownLayout(String),
+    UnknownHostKey(String),
+    UnknownChipKey(String),
+    Syntax(String),
+}
+
+impl HostKey {
+    pub fn from_char(c: char) -> Self {
+        HostKey::Char(c.to_ascii_lowercase())
+    }
+
+    /// Reads a key name as written in keymap files: a single character,
+    /// `space`, `enter`, `up`, `down`, `left`, `right`, `numpad_enter`, or
+    /// `numpad` followed by a digit or one of `/*-+.`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let lower = name.to_ascii_lowercase();
+        let key = match lower.as_str() {
+            "space" => HostKey::Char(' '),
+            "enter" => HostKey::Enter,
+            "up" => HostKey::Up,
+            "down" => HostKey::Down,
+            "left" => HostKey::Left,
+            "right" => HostKey::Right,
+            "numpad_enter" => HostKey::NumpadEnter,
+            _ => {
+                let (rest, numpad) = match lower.strip_prefix("numpad") {
+                    Some(rest) => (rest, true),
+                    None => (lower.as_str(), false),
+                };
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next(), numpad) {
+                    (Some(c), None, false) => HostKey::Char(c),
+                    (Some(c), None, true) if c.is_ascii_digit() || "/*-+.".contains(c) => {
+                        HostKey::Numpad(c)
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        Some(key)
+    }
+}
+
+impl Layout {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "qwerty" => Some(Layout::Qwerty),
+            "azerty" => Some(Layout::Azerty),
+            "dvorak" => Some(Layout::Dvorak),
+            "numpad" => Some(Layout::Numpad),
+            "hex" => Some(Layout::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn keymap(self) -> Keymap {
+        let rows = |keys: &str| Keymap::from_rows(keys).expect("built-in layouts have 16 keys");
+        match self {
+            Layout::Qwerty => rows("1234qwerasdfzxcv"),
+            // The top row types digits only with Shift held.
+            Layout::Azerty => rows("&é\"'azerqsdfwxcv"),
+            Layout::Dvorak => rows("1234',.paoeu;qjk"),
+            Layout::Numpad => {
+                let mut keymap = Keymap::new();
+                for digit in 0..10 {
+                    let c = std::char::from_digit(digit, 10).unwrap_or('0');
+                    keymap.bind(HostKey::Numpad(c), hex_key(digit as u8));
+                }
+                for (key, c) in ['/', '*', '-', '+'].iter().enumerate() {
+                    keymap.bind(HostKey::Numpad(*c), hex_key(0xA + key as u8));
+                }
+                keymap.bind(HostKey::NumpadEnter, ChipKey::CK_E);
+                keymap.bind(HostKey::Numpad('.'), ChipKey::CK_F);
+                keymap
+            }
+            Layout::Hex => {
+                let mut keymap = Keymap::new();
+                for digit in 0..16 {
+                    let c = std::char::from_digit(digit, 16).unwrap_or('0');
+                    keymap.bind(HostKey::Char(c), hex_key(digit as u8));
+                }
+                keymap
+            }
+        }
+    }
+}
+
+fn hex_key(digit: u8) -> ChipKey {
+    ChipKey::from_byte(digit).expect("hex digit")
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Keymap {
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    /// Binds 16 characters, read row by row, onto the keypad in
+    /// `KEYPAD_ORDER`.
+    pub fn from_rows(keys: &str) -> Result<Self, KeymapError> {
+        let keys: Vec<char> = keys.chars().collect();
+        if keys.len() != KEYPAD_ORDER.len() {
+            return Err(KeymapError::WrongLength(keys.len()));
+        }
+        let mut keymap = Keymap::new();
+        for (&c, &key) in keys.iter().zip(KEYPAD_ORDER.iter()) {
+            keymap.bind(HostKey::from_char(c), hex_key(key));
+        }
+        Ok(keymap)
+    }
+
+    /// Binds `host` to `key`, replacing whatever `host` was bound to.
+    pub fn bind(&mut self, host: HostKey, key: ChipKey) {
+        self.bindings.insert(host, key);
+    }
+
+    pub fn unbind(&mut self, host: HostKey) {
+        self.bindings.remove(&host);
+    }
+
+    /// Binds exactly `hosts` to `key`, dropping its other bindings.
+    pub fn rebind(&mut self, key: ChipKey, hosts: &[HostKey]) {
+        self.bindings.retain(|_, bound| *bound != key);
+        for &host in hosts {
+            self.bind(host, key);
+        }
+    }
+
+    pub fn get(&self, host: HostKey) -> Option<ChipKey> {
+        self.bindings.get(&host).copied()
+    }
+
+    pub fn host_keys(&self, key: ChipKey) -> impl Iterator<Item = HostKey> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, &bound)| bound == key)
+            .map(|(&host, _)| host)
+    }
+}
+
+impl KeyboardInput {
+    pub fn new(keymap: Keymap) -> Self {
+        KeyboardInput {
+            keymap,
+            held: BTreeSet::new(),
+        }
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Notes `host` going down, returning whether it is bound.
+    pub fn key_down(&mut self, host: HostKey) -> bool {
+        self.held.insert(host);
+        self.keymap.get(host).is_some()
+    }
+
+    pub fn key_up(&mut self, host: HostKey) {
+        self.held.remove(&host);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Holds exactly the keypad keys with at least one bound host key down.
+    pub fn apply(&self, keypad: &mut Keypad) {
+        keypad.release();
+        for &host in self.held.iter() {
+            if let Some(key) = self.keymap.get(host) {
+                keypad.press(key);
+            }
+        }
+    }
+}
+
+impl KeymapConfig {
+    pub fn parse(text: &str) -> Result<Self, KeymapError> {
+        let raw: RawConfig =
+            toml::from_str(text).map_err(|e| KeymapError::Syntax(e.message().to_string()))?;
+        let default = Section::parse(raw.layout, raw.keys)?;
+        let mut roms = BTreeMap::new();
+        for (name, section) in raw.roms {
+            roms.insert(name, Section::parse(section.layout, section.keys)?);
+        }
+        Ok(KeymapConfig { default, roms })
+    }
+
+    /// The keymap for the ROM with file name `rom`: its own layout or the
+    /// default one, QWERTY if neither is set, with the default bindings and
+    /// then its own applied on top.
+    pub fn keymap_for(&self, rom: &str) -> Keymap {
+        let own = self.roms.get(rom);
+        let layout = own
+            .and_then(|section| section.layout)
+            .or(self.default.layout)
+            .unwrap_or(Layout::Qwerty);
+        let mut keymap = layout.keymap();
+        for section in std::iter::once(&self.default).chain(own) {
+            for (key, hosts) in section.keys.iter() {
+                keymap.rebind(*key, hosts);
+            }
+        }
+        keymap
+    }
+}
+
+impl Section {
+    fn parse(
+        layout: Option<String>,
+        keys: BTreeMap<String, Vec<String>>,
+    ) -> Result<Self, KeymapError> {
+        let layout = match layout {
+            Some(name) => Some(Layout::parse(&name).ok_or(KeymapError::UnknownLayout(name))?),
+            None => None,
+        };
+        Ok(Section {
+            layout,
+            keys: parse_bindings(keys)?,
+        })
+    }
+}
+
+/// Reads `[keys]` tables, which list the host keys for keypad keys named by
+/// their hex digit.
+pub(crate) fn parse_bindings(
+    keys: BTreeMap<String, Vec<String>>,
+) -> Result<Vec<(ChipKey, Vec<HostKey>)>, KeymapError> {
+    let mut bindings = Vec::new();
+    for (key, hosts) in keys {
+        let chip_key = u8::from_str_radix(&key, 16)
+            .ok()
+            .and_then(ChipKey::from_byte)
+            .ok_or(KeymapError::UnknownChipKey(key))?;
+        let hosts = hosts
+            .into_iter()
+            .map(|name| HostKey::parse(&name).ok_or(KeymapError::UnknownHostKey(name)))
+            .collect::<Result<_, _>>()?;
+        bindings.push((chip_key, hosts));
+    }
+    Ok(bindings)
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostKey::Char(' ') => write!(f, "space"),
+            HostKey::Char(c) => write!(f, "{}", c),
+            HostKey::Numpad(c) => write!(f, "numpad{}", c),
+            HostKey::NumpadEnter => write!(f, "numpad_enter"),
+            HostKey::Enter => write!(f, "enter"),
+            HostKey::Up => write!(f, "up"),
+            HostKey::Down => write!(f, "down"),
+            HostKey::Left => write!(f, "left"),
+            HostKey::Right => write!(f, "right"),
+        }
+    }
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::WrongLength(len) => {
+                write!(f, "key layout must be 16 keys, not {}", len)
+            }
+            KeymapError::UnknownLayout(name) => write!(f, "unknown layout: {}", name),
+            KeymapError::UnknownHostKey(name) => write!(f, "unknown host key: {}", name),
+            KeymapError::UnknownChipKey(name) => write!(f, "not a hex key: {}", name),
+            KeymapError::Syntax(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn qwerty_maps_to_hex_keypad() {
+        let keymap = Layout::Qwerty.keymap();
+        let get = |c| keymap.get(HostKey::from_char(c));
+        assert_eq!(Some(ChipKey::CK_1), get('1'));
+        assert_eq!(Some(ChipKey::CK_C), get('4'));
+        assert_eq!(Some(ChipKey::CK_0), get('X'));
+        assert_eq!(Some(ChipKey::CK_F), get('v'));
+        assert_eq!(None, get('p'));
+    }
+
+    #[test]
+    fn layouts_cover_every_key() {
+        for &layout in [
+            Layout::Qwerty,
+            Layout::Azerty,
+            Layout::Dvorak,
+            Layout::Numpad,
+            Layout::Hex,
+        ]
+        .iter()
+        {
+            let keymap = layout.keymap();
+            for key in 0..16 {
+                let key = ChipKey::from_byte(key).unwrap();
+                assert_eq!(1, keymap.host_keys(key).count(), "{:?} {:?}", layout, key);
+            }
+        }
+        assert_eq!(
+            Some(ChipKey::CK_4),
+            Layout::Azerty.keymap().get(HostKey::Char('a'))
+        );
+    }
+
+    #[test]
+    fn azerty_uses_unshifted_top_row() {
+        let keymap = Layout::Azerty.keymap();
+        assert_eq!(Some(ChipKey::CK_1), keymap.get(HostKey::Char('&')));
+        assert_eq!(Some(ChipKey::CK_2), keymap.get(HostKey::Char('é')));
+        assert_eq!(Some(ChipKey::CK_C), keymap.get(HostKey::Char('\'')));
+        assert_eq!(None, keymap.get(HostKey::Char('1')));
+    }
+
+    #[test]
+    fn rows_must_have_sixteen_keys() {
+        assert_eq!(Err(KeymapError::WrongLength(4)), Keymap::from_rows("1234"));
+    }
+
+    #[test]
+    fn host_key_names_round_trip() {
+        for name in ["q", "space", "up", "numpad7", "numpad+", "numpad_enter"].iter() {
+            let key = HostKey::parse(name).unwrap();
+            assert_eq!(*name, key.to_string());
+        }
+        assert_eq!(None, HostKey::parse("numpadx"));
+        assert_eq!(None, HostKey::parse("escape"));
+    }
+
+    #[test]
+    fn key_stays_down_while_any_binding_is_held() {
+        let mut keymap = Layout::Qwerty.keymap();
+        keymap.bind(HostKey::Up, ChipKey::CK_5);
+        let mut input = KeyboardInput::new(keymap);
+        let mut keypad = Keypad::new();
+
+        assert!(input.key_down(HostKey::Char('w')));
+        input.key_down(HostKey::Up);
+        input.key_down(HostKey::Char('z'));
+        input.key_up(HostKey::Char('w'));
+        input.apply(&mut keypad);
+        assert!(keypad.is_pressed(ChipKey::CK_5));
+        assert!(keypad.is_pressed(ChipKey::CK_A));
+
+        input.key_up(HostKey::Up);
+        input.apply(&mut keypad);
+        assert!(!keypad.is_pressed(ChipKey::CK_5));
+        assert!(!input.key_down(HostKey::Char('p')));
+    }
+
+    #[test]
+    fn config_overrides_by_rom() {
+        let config = KeymapConfig::parse(
+            r#"
+            layout = "azerty"
+
+            [keys]
+            5 = ["z", "up"]
+
+            [roms."pong.ch8"]
+            layout = "qwerty"
+
+            [roms."pong.ch8".keys]
+            1 = ["w"]
+            "#,
+        )
+        .unwrap();
+
+        let other = config.keymap_for("tetris.ch8");
+        assert_eq!(Some(ChipKey::CK_4), other.get(HostKey::Char('a')));
+        assert_eq!(Some(ChipKey::CK_5), other.get(HostKey::Up));
+        assert_eq!(Some(ChipKey::CK_5), other.get(HostKey::Char('z')));
+
+        let pong = config.keymap_for("pong.ch8");
+        assert_eq!(Some(ChipKey::CK_1), pong.get(HostKey::Char('w')));
+        assert_eq!(None, pong.get(HostKey::Char('1')));
+        assert_eq!(Some(ChipKey::CK_5), pong.get(HostKey::Up));
+    }
+
+    #[test]
+    fn config_reports_bad_names() {
+        assert_eq!(
+            Err(KeymapError::UnknownLayout("colemak".to_string())),
+            KeymapConfig::parse("layout = \"colemak\"")
+        );
+        assert_eq!(
+            Err(KeymapError::UnknownHostKey("escape".to_string())),
+            KeymapConfig::parse("[keys]\n5 = [\"escape\"]")
+        );
+        assert_eq!(
+            Err(KeymapError::UnknownChipKey("G".to_string())),
+            KeymapConfig::parse("[keys]\nG = [\"g\"]")
+        );
+    }
+}
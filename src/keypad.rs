@@ -1,5 +1,5 @@
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ChipKey {
     CK_0 = 0,
@@ -45,32 +45,60 @@ impl ChipKey {
     }
 }
 
-#[derive(Default)]
+/// The hex keypad. Any number of keys can be held at once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Keypad {
-    pressed_key: Option<ChipKey>,
+    // Bit n is set while key n is held.
+    pressed: u16,
 }
 
 impl Keypad {
     pub fn new() -> Self {
-        Keypad { pressed_key: None }
+        Keypad { pressed: 0 }
     }
 
     pub fn press(&mut self, key: ChipKey) {
-        self.pressed_key = Some(key);
+        self.pressed |= 1 << key as u8;
     }
 
+    pub fn release_key(&mut self, key: ChipKey) {
+        self.pressed &= !(1 << key as u8);
+    }
+
+    /// Releases every key.
     pub fn release(&mut self) {
-        self.pressed_key = None;
+        self.pressed = 0;
     }
 
     pub fn is_pressed(&self, key: ChipKey) -> bool {
-        match self.pressed_key {
-            Some(k) => k == key,
-            None => false,
-        }
+        self.pressed & (1 << key as u8) != 0
     }
 
+    /// The lowest held key, if any.
     pub fn get_pressed(&self) -> Option<ChipKey> {
-        self.pressed_key
+        match self.pressed {
+            0 => None,
+            pressed => ChipKey::from_byte(pressed.trailing_zeros() as u8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn keys_are_held_together() {
+        let mut keypad = Keypad::new();
+        keypad.press(ChipKey::CK_9);
+        keypad.press(ChipKey::CK_2);
+        assert!(keypad.is_pressed(ChipKey::CK_9));
+        assert_eq!(Some(ChipKey::CK_2), keypad.get_pressed());
+
+        keypad.release_key(ChipKey::CK_2);
+        assert_eq!(Some(ChipKey::CK_9), keypad.get_pressed());
+        keypad.release();
+        assert_eq!(None, keypad.get_pressed());
     }
 }
@@ -1,4 +1,50 @@
+//! A CHIP-8 interpreter.
+//!
+//! The interpreter itself (`cpu`, `memory`, `graphics`, `keypad`,
+//! `instruction`, `quirks`, `variant`, `timing`, the `runtime` driving it and
+//! the `phosphor` filter) builds without the standard library. Tracing,
+//! rewinding, the debugger, the test harness, screenshots, recordings,
+//! keymaps, gamepad profiles, the ROM database, per-ROM config files, ROM
+//! analysis, control-flow graphs, symbol files and the binaries need the
+//! default `std` feature.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod control_flow;
 pub mod cpu;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod gamepad;
 pub mod graphics;
+#[cfg(feature = "std")]
+pub mod harness;
+pub mod instruction;
+#[cfg(feature = "std")]
+pub mod keymap;
 pub mod keypad;
 pub mod memory;
+pub mod phosphor;
+pub mod quirks;
+#[cfg(feature = "std")]
+pub mod recording;
+#[cfg(feature = "std")]
+pub mod rewind;
+pub mod runtime;
+#[cfg(feature = "std")]
+pub mod screenshot;
+#[cfg(feature = "std")]
+pub mod symbols;
+pub mod timing;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod trace_diff;
+pub mod variant;
@@ -1,8 +1,226 @@
+use crate::variant::Variant;
+use core::fmt;
+use core::ops::Range;
+
 pub const CHIP8_RAM: usize = 4096;
+pub const PROGRAM_START: usize = 0x200;
+pub const FONT_ADDRESS: usize = 0x050;
+pub const FONT_SPRITE_SIZE: usize = 5;
+pub const MAX_STACK_DEPTH: usize = 16;
+pub const VIP_STACK_ADDRESS: usize = 0xEA0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Interpreter,
+    Font,
+    Program,
+    Stack,
+    Display,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What happens when a program writes into a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    ReadWrite,
+    /// The write goes ahead but is remembered as a `Violation`.
+    Log,
+    /// The write is refused with `MemoryError::Protected`.
+    Fault,
+}
+
+/// A program write that touched a protected region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub address: usize,
+    pub len: usize,
+    pub region: RegionKind,
+}
+
+/// A program write over bytes that had already been executed as code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SelfModification {
+    pub address: usize,
+    pub len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryError {
+    OutOfBounds { address: usize, len: usize },
+    Protected(Violation),
+}
+
+const STANDARD_REGIONS: [Region; 4] = [
+    Region::new(RegionKind::Interpreter, 0x000, FONT_ADDRESS),
+    Region::new(RegionKind::Font, FONT_ADDRESS, FONT_ADDRESS + FONT.len()),
+    Region::new(
+        RegionKind::Interpreter,
+        FONT_ADDRESS + FONT.len(),
+        PROGRAM_START,
+    ),
+    Region::new(RegionKind::Program, PROGRAM_START, CHIP8_RAM),
+];
+
+// The COSMAC VIP keeps its stack, interpreter variables and display buffer
+// at the top of its 4K of RAM.
+const VIP_REGIONS: [Region; 7] = [
+    Region::new(RegionKind::Interpreter, 0x000, FONT_ADDRESS),
+    Region::new(RegionKind::Font, FONT_ADDRESS, FONT_ADDRESS + FONT.len()),
+    Region::new(
+        RegionKind::Interpreter,
+        FONT_ADDRESS + FONT.len(),
+        PROGRAM_START,
+    ),
+    Region::new(RegionKind::Program, PROGRAM_START, VIP_STACK_ADDRESS),
+    Region::new(RegionKind::Stack, VIP_STACK_ADDRESS, 0xED0),
+    Region::new(RegionKind::Interpreter, 0xED0, 0xF00),
+    Region::new(RegionKind::Display, 0xF00, CHIP8_RAM),
+];
+
+const FONT: [u8; 16 * FONT_SPRITE_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const VIP_FONT: [u8; 16 * FONT_SPRITE_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// The hex digit sprites `Fx29` points at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Font {
+    /// The font most modern interpreters use, as in Octo.
+    #[default]
+    Octo,
+    /// The font in the COSMAC VIP's interpreter.
+    Vip,
+}
+
+impl Font {
+    /// Reads a font name as used by the chip-8-database, `octo` or `vip`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "octo" => Some(Font::Octo),
+            "vip" => Some(Font::Vip),
+            _ => None,
+        }
+    }
+
+    pub fn sprites(self) -> &'static [u8; 16 * FONT_SPRITE_SIZE] {
+        match self {
+            Font::Octo => &FONT,
+            Font::Vip => &VIP_FONT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackError {
+    Overflow,
+    Underflow,
+}
+
+/// Where return addresses are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackLocation {
+    /// Outside of addressable memory, as most interpreters do.
+    Internal,
+    /// In RAM growing upwards from `VIP_STACK_ADDRESS` as big-endian words,
+    /// like the COSMAC VIP, so ROMs that write there corrupt their returns.
+    VipRam,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct CallStack {
+    frames: [usize; MAX_STACK_DEPTH],
+    len: usize,
+    depth: usize,
+    location: StackLocation,
+}
 
+/// One bit per byte of RAM.
+#[derive(Clone, Copy)]
+struct AddressSet([u64; CHIP8_RAM / 64]);
+
+/// Which bytes have run as code, and which of those were later overwritten.
+#[derive(Clone, Copy)]
+pub(crate) struct CodeMap {
+    executed: AddressSet,
+    modified: AddressSet,
+}
+
+#[derive(Clone)]
 pub struct Memory {
     bytes: [u8; CHIP8_RAM],
-    callstack: Vec<usize>,
+    callstack: CallStack,
+    protection: [Protection; 5],
+    violation: Option<Violation>,
+    code: CodeMap,
+    self_modification: Option<SelfModification>,
+    written: Option<Range<usize>>,
+}
+
+impl AddressSet {
+    const fn new() -> Self {
+        AddressSet([0; CHIP8_RAM / 64])
+    }
+
+    fn insert(&mut self, address: usize) {
+        self.0[address / 64] |= 1 << (address % 64);
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        self.0[address / 64] & (1 << (address % 64)) != 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..CHIP8_RAM).filter(move |&address| self.contains(address))
+    }
+}
+
+impl Region {
+    const fn new(kind: RegionKind, start: usize, end: usize) -> Self {
+        Region { kind, start, end }
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
 }
 
 impl Default for Memory {
@@ -13,29 +231,491 @@ impl Default for Memory {
 
 impl Memory {
     pub fn new() -> Self {
-        Memory {
+        Memory::for_variant(Variant::default())
+    }
+
+    pub fn for_variant(variant: Variant) -> Self {
+        Memory::with_stack(variant.stack_depth(), StackLocation::Internal)
+    }
+
+    /// Creates memory whose call stack holds up to `depth` return addresses,
+    /// capped at `MAX_STACK_DEPTH`.
+    pub fn with_stack(depth: usize, location: StackLocation) -> Self {
+        let mut memory = Memory {
             bytes: [0; CHIP8_RAM],
-            callstack: vec![0; 16],
-        }
+            callstack: CallStack {
+                frames: [0; MAX_STACK_DEPTH],
+                len: 0,
+                depth: depth.min(MAX_STACK_DEPTH),
+                location,
+            },
+            protection: [Protection::Log; 5],
+            violation: None,
+            code: CodeMap {
+                executed: AddressSet::new(),
+                modified: AddressSet::new(),
+            },
+            self_modification: None,
+            written: None,
+        };
+        memory.set_protection(RegionKind::Program, Protection::ReadWrite);
+        memory.set_font(Font::default());
+        memory
+    }
+
+    /// Replaces the digit sprites with `font`'s.
+    pub fn set_font(&mut self, font: Font) {
+        self.store(FONT_ADDRESS, font.sprites());
+    }
+
+    /// Copies `size` bytes starting at `offset`. Prefer `slice`, which does
+    /// not allocate.
+    #[cfg(feature = "std")]
+    pub fn load(&self, offset: usize, size: usize) -> Result<Vec<u8>, MemoryError> {
+        self.slice(offset, size).map(<[u8]>::to_vec)
     }
 
-    pub fn load(&self, offset: usize, size: usize) -> Vec<u8> {
-        self.bytes[offset..offset + size].to_vec()
+    /// Borrows `len` bytes at `offset`, failing like `write` if they run
+    /// past the end of RAM.
+    pub fn slice(&self, offset: usize, len: usize) -> Result<&[u8], MemoryError> {
+        self.bytes
+            .get(offset..offset + len)
+            .ok_or(MemoryError::OutOfBounds {
+                address: offset,
+                len,
+            })
     }
 
+    /// Borrows `len` bytes at `offset` for writing, bypassing protection like
+    /// `store` does.
+    pub fn slice_mut(&mut self, offset: usize, len: usize) -> &mut [u8] {
+        self.mark_written(offset..offset + len);
+        &mut self.bytes[offset..offset + len]
+    }
+
+    /// Reads the big-endian word at `address`.
+    pub fn read_word(&self, address: usize) -> Result<u16, MemoryError> {
+        let word = self.slice(address, 2)?;
+        Ok(u16::from_be_bytes([word[0], word[1]]))
+    }
+
+    /// Writes `data` at `offset` regardless of protection, as a host loading
+    /// a ROM or poking memory from the debugger would.
     pub fn store(&mut self, offset: usize, data: &[u8]) {
-        self.bytes[offset..offset + data.len()].copy_from_slice(data);
+        self.slice_mut(offset, data.len()).copy_from_slice(data);
+    }
+
+    /// Takes the smallest range covering every store since the last call.
+    #[cfg(feature = "std")]
+    pub(crate) fn take_written(&mut self) -> Option<Range<usize>> {
+        self.written.take()
+    }
+
+    fn mark_written(&mut self, range: Range<usize>) {
+        self.written = match self.written.take() {
+            Some(written) => Some(written.start.min(range.start)..written.end.max(range.end)),
+            None => Some(range),
+        };
+    }
+
+    /// Writes `data` at `offset` on behalf of the running program, honouring
+    /// the protection of every region it touches.
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryError> {
+        let end = offset + data.len();
+        if end > CHIP8_RAM {
+            return Err(MemoryError::OutOfBounds {
+                address: offset,
+                len: data.len(),
+            });
+        }
+
+        let mut logged = None;
+        for region in self.regions() {
+            if region.start >= end || offset >= region.end {
+                continue;
+            }
+            let violation = Violation {
+                address: offset,
+                len: data.len(),
+                region: region.kind,
+            };
+            match self.protection(region.kind) {
+                Protection::ReadWrite => {}
+                Protection::Log => logged = logged.or(Some(violation)),
+                Protection::Fault => return Err(MemoryError::Protected(violation)),
+            }
+        }
+        if logged.is_some() {
+            self.violation = logged;
+        }
+        self.store(offset, data);
+
+        let mut modified = false;
+        for address in offset..end {
+            if self.code.executed.contains(address) {
+                self.code.modified.insert(address);
+                modified = true;
+            }
+        }
+        if modified {
+            self.self_modification = Some(SelfModification {
+                address: offset,
+                len: data.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Records that the instruction at `address` is about to run.
+    pub fn mark_executed(&mut self, address: usize) {
+        for address in address..(address + 2).min(CHIP8_RAM) {
+            self.code.executed.insert(address);
+        }
+    }
+
+    pub fn was_executed(&self, address: usize) -> bool {
+        address < CHIP8_RAM && self.code.executed.contains(address)
+    }
+
+    /// Takes the most recent program write over already executed code.
+    pub fn take_self_modification(&mut self) -> Option<SelfModification> {
+        self.self_modification.take()
+    }
+
+    /// Every address that ran as code and was later overwritten by the
+    /// program, in ascending order.
+    pub fn self_modified(&self) -> impl Iterator<Item = usize> + '_ {
+        self.code.modified.iter()
+    }
+
+    /// The layout of memory, which follows the COSMAC VIP when the call stack
+    /// lives in RAM.
+    pub fn regions(&self) -> &'static [Region] {
+        match self.callstack.location {
+            StackLocation::Internal => &STANDARD_REGIONS,
+            StackLocation::VipRam => &VIP_REGIONS,
+        }
+    }
+
+    pub fn region_at(&self, address: usize) -> Option<Region> {
+        self.regions().iter().copied().find(|r| r.contains(address))
+    }
+
+    pub fn protection(&self, kind: RegionKind) -> Protection {
+        self.protection[kind as usize]
+    }
+
+    pub fn set_protection(&mut self, kind: RegionKind, protection: Protection) {
+        self.protection[kind as usize] = protection;
+    }
+
+    /// Takes the most recent logged write into a protected region.
+    pub fn take_violation(&mut self) -> Option<Violation> {
+        self.violation.take()
     }
 
     pub fn is_callstack_empty(&self) -> bool {
-        self.callstack.is_empty()
+        self.callstack.len == 0
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.callstack.depth
+    }
+
+    pub fn push(&mut self, address: usize) -> Result<(), StackError> {
+        let level = self.callstack.len;
+        if level == self.callstack.depth {
+            return Err(StackError::Overflow);
+        }
+        match self.callstack.location {
+            StackLocation::Internal => self.callstack.frames[level] = address,
+            StackLocation::VipRam => {
+                let word = (address as u16).to_be_bytes();
+                self.store(VIP_STACK_ADDRESS + 2 * level, &word);
+            }
+        }
+        self.callstack.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<usize, StackError> {
+        if self.callstack.len == 0 {
+            return Err(StackError::Underflow);
+        }
+        self.callstack.len -= 1;
+        Ok(self.frame(self.callstack.len))
+    }
+
+    /// Iterates over the return addresses on the call stack, starting with
+    /// the most recent call.
+    pub fn frames(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.callstack.len)
+            .rev()
+            .map(move |level| self.frame(level))
+    }
+
+    fn frame(&self, level: usize) -> usize {
+        match self.callstack.location {
+            StackLocation::Internal => self.callstack.frames[level],
+            StackLocation::VipRam => {
+                let at = VIP_STACK_ADDRESS + 2 * level;
+                u16::from_be_bytes([self.bytes[at], self.bytes[at + 1]]) as usize
+            }
+        }
+    }
+
+    /// All of RAM.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.slice_mut(0, CHIP8_RAM)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn callstack(&self) -> CallStack {
+        self.callstack
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_callstack(&mut self, callstack: CallStack) {
+        self.callstack = callstack;
+    }
+
+    pub(crate) fn code_map(&self) -> CodeMap {
+        self.code
+    }
+
+    pub(crate) fn set_code_map(&mut self, code: CodeMap) {
+        self.code = code;
+    }
+
+    pub(crate) fn protections(&self) -> [Protection; 5] {
+        self.protection
+    }
+
+    pub(crate) fn set_protections(&mut self, protection: [Protection; 5]) {
+        self.protection = protection;
+    }
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackError::Overflow => write!(f, "call stack overflow"),
+            StackError::Underflow => write!(f, "return with an empty call stack"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StackError {}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RegionKind::Interpreter => "interpreter",
+            RegionKind::Font => "font",
+            RegionKind::Program => "program",
+            RegionKind::Stack => "stack",
+            RegionKind::Display => "display",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-byte write at {:#05X} into the {} region",
+            self.len, self.address, self.region
+        )
+    }
+}
+
+impl fmt::Display for SelfModification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-byte write at {:#05X} over executed code",
+            self.len, self.address
+        )
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::OutOfBounds { address, len } => {
+                write!(
+                    f,
+                    "{}-byte access at {:#05X} runs past the end of RAM",
+                    len, address
+                )
+            }
+            MemoryError::Protected(violation) => write!(f, "protected {}", violation),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MemoryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn new_callstack_is_empty() {
+        let mut memory = Memory::new();
+        assert_eq!(true, memory.is_callstack_empty());
+        assert_eq!(Err(StackError::Underflow), memory.pop());
+    }
+
+    #[test]
+    fn callstack_depth_follows_variant() {
+        assert_eq!(
+            12,
+            Memory::for_variant(Variant::OriginalChip8).stack_depth()
+        );
+        assert_eq!(16, Memory::for_variant(Variant::SuperChip).stack_depth());
+    }
+
+    #[test]
+    fn push_past_depth_overflows() {
+        let mut memory = Memory::with_stack(2, StackLocation::Internal);
+        assert_eq!(Ok(()), memory.push(0x202));
+        assert_eq!(Ok(()), memory.push(0x302));
+        assert_eq!(Err(StackError::Overflow), memory.push(0x402));
+        assert_eq!(vec![0x302, 0x202], memory.frames().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn write_to_program_region() {
+        let mut memory = Memory::new();
+        assert_eq!(Ok(()), memory.write(0x300, &[1, 2]));
+        assert_eq!(None, memory.take_violation());
+        assert_eq!(vec![1, 2], memory.load(0x300, 2).unwrap());
+    }
+
+    #[test]
+    fn slice_mut_counts_as_written() {
+        let mut memory = Memory::new();
+        memory.take_written();
+        memory.slice_mut(0x300, 2)[1] = 7;
+        memory.store(0x280, &[1]);
+        assert_eq!(Some(0x280..0x302), memory.take_written());
+        assert_eq!(&[0, 7], memory.slice(0x300, 2).unwrap());
+    }
+
+    #[test]
+    fn write_to_font_is_logged() {
+        let mut memory = Memory::new();
+        assert_eq!(Ok(()), memory.write(FONT_ADDRESS - 1, &[1, 2]));
+        assert_eq!(
+            Some(Violation {
+                address: FONT_ADDRESS - 1,
+                len: 2,
+                region: RegionKind::Interpreter,
+            }),
+            memory.take_violation()
+        );
+        assert_eq!(vec![1, 2], memory.load(FONT_ADDRESS - 1, 2).unwrap());
     }
 
-    pub fn push(&mut self, address: usize) {
-        self.callstack.push(address);
+    #[test]
+    fn write_to_faulting_region_is_refused() {
+        let mut memory = Memory::new();
+        memory.set_protection(RegionKind::Font, Protection::Fault);
+        let result = memory.write(FONT_ADDRESS, &[0xFF]);
+        assert_eq!(
+            Err(MemoryError::Protected(Violation {
+                address: FONT_ADDRESS,
+                len: 1,
+                region: RegionKind::Font,
+            })),
+            result
+        );
+        assert_eq!(vec![0xF0], memory.load(FONT_ADDRESS, 1).unwrap());
     }
 
-    pub fn pop(&mut self) -> Option<usize> {
-        self.callstack.pop()
+    #[test]
+    fn write_past_end_of_ram() {
+        let mut memory = Memory::new();
+        assert_eq!(
+            Err(MemoryError::OutOfBounds {
+                address: 0xFFF,
+                len: 2
+            }),
+            memory.write(0xFFF, &[1, 2])
+        );
+    }
+
+    #[test]
+    fn read_past_end_of_ram() {
+        let memory = Memory::new();
+        let past_end = MemoryError::OutOfBounds {
+            address: 0xFFF,
+            len: 2,
+        };
+        assert_eq!(Err(past_end), memory.read_word(0xFFF));
+        assert_eq!(Err(past_end), memory.slice(0xFFF, 2));
+        assert_eq!(Ok(&[0][..]), memory.slice(0xFFF, 1));
+    }
+
+    #[test]
+    fn write_over_executed_code_is_flagged() {
+        let mut memory = Memory::new();
+        memory.mark_executed(0x200);
+        assert_eq!(Ok(()), memory.write(0x202, &[1]));
+        assert_eq!(None, memory.take_self_modification());
+
+        assert_eq!(Ok(()), memory.write(0x1FF, &[1, 2, 3]));
+        assert_eq!(
+            Some(SelfModification {
+                address: 0x1FF,
+                len: 3
+            }),
+            memory.take_self_modification()
+        );
+        assert_eq!(
+            vec![0x200, 0x201],
+            memory.self_modified().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn vip_layout_has_stack_and_display() {
+        let memory = Memory::with_stack(12, StackLocation::VipRam);
+        assert_eq!(
+            Some(RegionKind::Stack),
+            memory.region_at(0xEA0).map(|r| r.kind)
+        );
+        assert_eq!(
+            Some(RegionKind::Display),
+            memory.region_at(0xFFF).map(|r| r.kind)
+        );
+        assert_eq!(
+            Some(RegionKind::Program),
+            Memory::new().region_at(0xFFF).map(|r| r.kind)
+        );
+    }
+
+    #[test]
+    fn vip_stack_lives_in_ram() {
+        let mut memory = Memory::with_stack(12, StackLocation::VipRam);
+        memory.push(0x202).unwrap();
+        memory.push(0x34A).unwrap();
+        assert_eq!(
+            vec![0x02, 0x02, 0x03, 0x4A],
+            memory.load(VIP_STACK_ADDRESS, 4).unwrap()
+        );
+
+        memory.store(VIP_STACK_ADDRESS + 2, &[0x05, 0x00]);
+        assert_eq!(Ok(0x500), memory.pop());
+        assert_eq!(Ok(0x202), memory.pop());
     }
 }
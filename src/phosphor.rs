@@ -0,0 +1,174 @@
+//! Softens flicker by blending recent frames.
+//!
+//! CHIP-8 games move sprites by erasing and redrawing them with XOR, so a
+//! sprite is often missing from the frame the host happens to show. A
+//! [`Phosphor`] is fed every frame and keeps an intensity per pixel that
+//! frontends can draw instead of the raw screen. It only reads `Graphics`,
+//! so emulation is unaffected.
+
+use crate::graphics::{Graphics, GRAPHICS_HEIGHT, GRAPHICS_VBUFFER, GRAPHICS_WIDTH};
+
+/// The most frames `Persistence::Blend` can look back over.
+pub const MAX_BLEND_FRAMES: usize = 8;
+pub const FULL_INTENSITY: u8 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Persistence {
+    /// Lit pixels are at full intensity and fade to `keep / 256` of their
+    /// intensity every frame after they go dark, like a CRT's phosphor.
+    Decay { keep: u8 },
+    /// Pixels lit in any of the last `frames` frames are at full intensity.
+    Blend { frames: usize },
+}
+
+#[derive(Clone)]
+pub struct Phosphor {
+    persistence: Persistence,
+    intensity: [u8; GRAPHICS_WIDTH * GRAPHICS_HEIGHT],
+    history: [[u8; GRAPHICS_VBUFFER]; MAX_BLEND_FRAMES],
+    next: usize,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence) -> Self {
+        let persistence = match persistence {
+            Persistence::Blend { frames } => Persistence::Blend {
+                frames: frames.clamp(1, MAX_BLEND_FRAMES),
+            },
+            decay => decay,
+        };
+        Phosphor {
+            persistence,
+            intensity: [0; GRAPHICS_WIDTH * GRAPHICS_HEIGHT],
+            history: [[0; GRAPHICS_VBUFFER]; MAX_BLEND_FRAMES],
+            next: 0,
+        }
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    /// Adds the screen at the end of a frame.
+    pub fn update(&mut self, graphics: &Graphics) {
+        match self.persistence {
+            Persistence::Decay { keep } => {
+                for y in 0..GRAPHICS_HEIGHT {
+                    for x in 0..GRAPHICS_WIDTH {
+                        let pixel = &mut self.intensity[y * GRAPHICS_WIDTH + x];
+                        *pixel = if graphics.is_pixel_set(x, y) {
+                            FULL_INTENSITY
+                        } else {
+                            (*pixel as u16 * keep as u16 / 256) as u8
+                        };
+                    }
+                }
+            }
+            Persistence::Blend { frames } => {
+                self.history[self.next].copy_from_slice(graphics.bytes());
+                self.next = (self.next + 1) % MAX_BLEND_FRAMES;
+                let mut lit = [0; GRAPHICS_VBUFFER];
+                for back in 1..=frames {
+                    let frame =
+                        &self.history[(self.next + MAX_BLEND_FRAMES - back) % MAX_BLEND_FRAMES];
+                    for (lit, byte) in lit.iter_mut().zip(frame.iter()) {
+                        *lit |= byte;
+                    }
+                }
+                for (i, pixel) in self.intensity.iter_mut().enumerate() {
+                    let on = lit[i / 8] & (0x80 >> (i % 8)) != 0;
+                    *pixel = if on { FULL_INTENSITY } else { 0 };
+                }
+            }
+        }
+    }
+
+    pub fn intensity(&self, x: usize, y: usize) -> u8 {
+        self.intensity[y * GRAPHICS_WIDTH + x]
+    }
+
+    /// Intensities row by row, one byte per pixel.
+    pub fn intensities(&self) -> &[u8] {
+        &self.intensity
+    }
+
+    /// A screen with the pixels at `min_intensity` or brighter lit, for
+    /// frontends that can only draw on and off.
+    pub fn to_graphics(&self, min_intensity: u8) -> Graphics {
+        let mut graphics = Graphics::new();
+        for y in 0..GRAPHICS_HEIGHT {
+            for x in 0..GRAPHICS_WIDTH {
+                graphics.set_pixel(x, y, self.intensity(x, y) >= min_intensity.max(1));
+            }
+        }
+        graphics
+    }
+
+    /// Forgets earlier frames, e.g. after loading a state.
+    pub fn reset(&mut self) {
+        *self = Phosphor::new(self.persistence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn frames(lit: &[bool]) -> impl Iterator<Item = Graphics> + '_ {
+        lit.iter().map(|&lit| {
+            let mut graphics = Graphics::new();
+            graphics.set_pixel(3, 1, lit);
+            graphics
+        })
+    }
+
+    #[test]
+    fn decay_fades_dark_pixels() {
+        let mut phosphor = Phosphor::new(Persistence::Decay { keep: 128 });
+        let mut seen = Vec::new();
+        for graphics in frames(&[true, false, false, true]) {
+            phosphor.update(&graphics);
+            seen.push(phosphor.intensity(3, 1));
+        }
+        assert_eq!(vec![255, 127, 63, 255], seen);
+        assert_eq!(0, phosphor.intensity(2, 1));
+    }
+
+    #[test]
+    fn blend_holds_pixels_for_its_frames() {
+        let mut phosphor = Phosphor::new(Persistence::Blend { frames: 2 });
+        let mut seen = Vec::new();
+        for graphics in frames(&[false, true, false, false, false]) {
+            phosphor.update(&graphics);
+            seen.push(phosphor.intensity(3, 1));
+        }
+        assert_eq!(vec![0, 255, 255, 0, 0], seen);
+    }
+
+    #[test]
+    fn to_graphics_thresholds_intensity() {
+        let mut phosphor = Phosphor::new(Persistence::Decay { keep: 128 });
+        for graphics in frames(&[true, false]) {
+            phosphor.update(&graphics);
+        }
+        assert!(phosphor.to_graphics(100).is_pixel_set(3, 1));
+        assert!(!phosphor.to_graphics(128).is_pixel_set(3, 1));
+        assert!(!phosphor.to_graphics(0).is_pixel_set(2, 1));
+    }
+
+    #[test]
+    fn reset_forgets_history() {
+        let mut phosphor = Phosphor::new(Persistence::Blend { frames: 20 });
+        assert_eq!(
+            Persistence::Blend {
+                frames: MAX_BLEND_FRAMES
+            },
+            phosphor.persistence()
+        );
+        phosphor.update(&frames(&[true]).next().unwrap());
+        phosphor.reset();
+        phosphor.update(&Graphics::new());
+        assert!(phosphor.intensities().iter().all(|&i| i == 0));
+    }
+}
@@ -0,0 +1,71 @@
+//! Behaviours that differ between CHIP-8 interpreters.
+
+use crate::variant::Variant;
+
+/// Switches for the places where interpreters disagree. The default is what
+/// most modern interpreters and ROMs expect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `Dxyn` waits for the start of the next frame before drawing, as on
+    /// the COSMAC VIP, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// Sprites wrap around the edges of the screen instead of being clipped.
+    /// Their starting position always wraps.
+    pub wrap_sprites: bool,
+}
+
+/// Quirks to change from a variant's behaviour. Each is left alone while
+/// `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuirkOverrides {
+    pub display_wait: Option<bool>,
+    pub wrap_sprites: Option<bool>,
+}
+
+impl Quirks {
+    /// The behaviour of `variant`'s original interpreter.
+    pub fn for_variant(variant: Variant) -> Self {
+        match variant {
+            Variant::OriginalChip8 => Quirks {
+                display_wait: true,
+                ..Quirks::default()
+            },
+            Variant::XoChip => Quirks {
+                wrap_sprites: true,
+                ..Quirks::default()
+            },
+            Variant::ModernChip8 | Variant::SuperChip => Quirks::default(),
+        }
+    }
+}
+
+impl QuirkOverrides {
+    /// Overrides every quirk to match `quirks`.
+    pub fn all(quirks: Quirks) -> Self {
+        QuirkOverrides {
+            display_wait: Some(quirks.display_wait),
+            wrap_sprites: Some(quirks.wrap_sprites),
+        }
+    }
+
+    /// The overrides needed to turn `base` into `quirks`.
+    pub fn between(base: Quirks, quirks: Quirks) -> Self {
+        let changed = |base: bool, quirk: bool| if base != quirk { Some(quirk) } else { None };
+        QuirkOverrides {
+            display_wait: changed(base.display_wait, quirks.display_wait),
+            wrap_sprites: changed(base.wrap_sprites, quirks.wrap_sprites),
+        }
+    }
+
+    /// Takes the quirks `over` sets, keeping the rest.
+    pub fn merge(&mut self, over: QuirkOverrides) {
+        self.display_wait = over.display_wait.or(self.display_wait);
+        self.wrap_sprites = over.wrap_sprites.or(self.wrap_sprites);
+    }
+
+    pub fn apply(self, mut quirks: Quirks) -> Quirks {
+        quirks.display_wait = self.display_wait.unwrap_or(quirks.display_wait);
+        quirks.wrap_sprites = self.wrap_sprites.unwrap_or(quirks.wrap_sprites);
+        quirks
+    }
+}
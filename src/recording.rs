@@ -0,0 +1,204 @@
+//! Records frames to an animated GIF or a numbered PNG sequence.
+//!
+//! A [`Recording`] is given the screen once per 60 Hz frame and keeps only
+//! the frames where it changed, along with the frame each one appeared on,
+//! so the animation plays back at the speed the ROM ran.
+
+use crate::graphics::Graphics;
+use crate::runtime::{Host, FRAMES_PER_SECOND};
+use crate::screenshot::Screenshot;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+pub struct Recording {
+    frame: u32,
+    range: Range<u32>,
+    frames: Vec<Captured>,
+}
+
+struct Captured {
+    graphics: Graphics,
+    // The frame it first appeared on.
+    start: u32,
+}
+
+impl Recording {
+    /// Records every frame from the first one passed to `capture`.
+    pub fn new() -> Self {
+        Recording {
+            frame: 0,
+            range: 0..u32::MAX,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Only records frames numbered within `range`, counting from 0.
+    pub fn frames(mut self, range: Range<u32>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Takes the screen at the end of a frame.
+    pub fn capture(&mut self, graphics: &Graphics) {
+        let changed = match self.frames.last() {
+            Some(last) => last.graphics.bytes() != graphics.bytes(),
+            None => true,
+        };
+        if self.range.contains(&self.frame) && changed {
+            self.frames.push(Captured {
+                graphics: graphics.clone(),
+                start: self.frame,
+            });
+        }
+        self.frame += 1;
+    }
+
+    /// Number of distinct frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// How long each recorded frame stays on screen, in hundredths of a
+    /// second. Frame boundaries are rounded to the nearest hundredth, so a
+    /// frame shown for one sixtieth lasts 1 or 2.
+    pub fn delays(&self) -> Vec<u16> {
+        let end = self.frame.min(self.range.end);
+        let centiseconds = |frame: u32| {
+            let hundredths = frame as u64 * 100;
+            let fps = FRAMES_PER_SECOND as u64;
+            ((hundredths + fps / 2) / fps) as u16
+        };
+        self.frames
+            .iter()
+            .enumerate()
+            .map(|(i, captured)| {
+                let next = self.frames.get(i + 1).map_or(end, |next| next.start);
+                centiseconds(next) - centiseconds(captured.start)
+            })
+            .collect()
+    }
+
+    /// Encodes the recording as a looping GIF. Many viewers slow down
+    /// frames shorter than 2 hundredths, which fast animation may have.
+    pub fn gif(&self, screenshot: &Screenshot) -> Vec<u8> {
+        let palette = screenshot.palette;
+        let mut colours = Vec::with_capacity(6);
+        colours.extend_from_slice(&palette.off);
+        colours.extend_from_slice(&palette.on);
+
+        let (width, height) = (screenshot.width() as u16, screenshot.height() as u16);
+        let mut gif = Vec::new();
+        {
+            // Writing into memory only fails on frames of the wrong size.
+            let mut encoder =
+                gif::Encoder::new(&mut gif, width, height, &colours).expect("GIF header");
+            encoder.set_repeat(gif::Repeat::Infinite).expect("GIF loop");
+            for (captured, delay) in self.frames.iter().zip(self.delays()) {
+                let pixels = screenshot.indexed(&captured.graphics);
+                let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+                frame.delay = delay;
+                encoder.write_frame(&frame).expect("frame matches header");
+            }
+        }
+        gif
+    }
+
+    pub fn save_gif<P: AsRef<Path>>(&self, screenshot: &Screenshot, path: P) -> io::Result<()> {
+        std::fs::write(path, self.gif(screenshot))
+    }
+
+    /// Writes each recorded frame into `dir` as `frame-NNNNNN.png`, numbered
+    /// by the frame it appeared on.
+    pub fn save_pngs<P: AsRef<Path>>(&self, screenshot: &Screenshot, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        for captured in self.frames.iter() {
+            let path = dir.join(format!("frame-{:06}.png", captured.start));
+            std::fs::write(path, screenshot.png(&captured.graphics))?;
+        }
+        Ok(())
+    }
+
+    /// Saves a GIF if `path` ends in `.gif`, or a PNG sequence into the
+    /// directory `path` otherwise.
+    pub fn save<P: AsRef<Path>>(&self, screenshot: &Screenshot, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let is_gif = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+        if is_gif {
+            self.save_gif(screenshot, path)
+        } else {
+            self.save_pngs(screenshot, path)
+        }
+    }
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Recording::new()
+    }
+}
+
+impl Host for Recording {
+    fn frame(&mut self, graphics: &Graphics) {
+        self.capture(graphics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn lit(x: usize) -> Graphics {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(x, 0, true);
+        graphics
+    }
+
+    #[test]
+    fn only_changes_are_kept() {
+        let mut recording = Recording::new();
+        for graphics in [lit(0), lit(0), lit(1), lit(1), lit(1), lit(0)].iter() {
+            recording.capture(graphics);
+        }
+        assert_eq!(3, recording.len());
+        // Boundaries at frames 0, 2, 5 and 6 are 0, 3, 8 and 10 hundredths.
+        assert_eq!(vec![3, 5, 2], recording.delays());
+    }
+
+    #[test]
+    fn frames_outside_the_range_are_skipped() {
+        let mut recording = Recording::new().frames(2..4);
+        for x in 0..6 {
+            recording.capture(&lit(x));
+        }
+        assert_eq!(2, recording.len());
+        assert_eq!(vec![2, 2], recording.delays());
+    }
+
+    #[test]
+    fn gif_holds_every_recorded_frame() {
+        let mut recording = Recording::new();
+        for graphics in [lit(0), lit(0), lit(1)].iter() {
+            recording.capture(graphics);
+        }
+        let gif = recording.gif(&Screenshot::new().scale(2));
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).unwrap();
+        assert_eq!((128, 64), (decoder.width(), decoder.height()));
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer[..4].to_vec()));
+        }
+        assert_eq!(vec![(3, vec![1, 1, 0, 0]), (2, vec![0, 0, 1, 1])], frames);
+    }
+}
@@ -0,0 +1,425 @@
+//! Rewinding and reverse stepping.
+//!
+//! [`History`] stands between the host and the `Cpu`: every instruction,
+//! timer tick and keypad change goes through it so it can be replayed. Every
+//! `interval` instructions it saves a snapshot, storing most of them as the
+//! bytes of memory and the framebuffer that differ from the last full
+//! keyframe. Going back to any earlier point restores the nearest snapshot
+//! at or before it and replays the recorded operations from there.
+
+use crate::cpu::{Cpu, CpuError, DisplayWait, Registers, Snapshot};
+use crate::keypad::Keypad;
+use crate::memory::CallStack;
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
+
+/// How many snapshots share a keyframe, the first of them being the keyframe
+/// itself.
+const KEYFRAME_INTERVAL: usize = 32;
+
+#[derive(Clone, Copy)]
+enum Op {
+    Tick,
+    Timers,
+    Input(Keypad),
+}
+
+/// The bytes of a buffer that differ from a base buffer of equal length.
+struct ByteDelta(Vec<(u16, u8)>);
+
+impl ByteDelta {
+    fn between(base: &[u8], current: &[u8]) -> Self {
+        let changes = base
+            .iter()
+            .zip(current.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (_, &new))| (i as u16, new))
+            .collect();
+        ByteDelta(changes)
+    }
+
+    fn apply(&self, target: &mut [u8]) {
+        for &(i, byte) in self.0.iter() {
+            target[i as usize] = byte;
+        }
+    }
+}
+
+/// A snapshot stored relative to a keyframe, keeping only the changed bytes
+/// of memory and the framebuffer alongside the small parts of the state.
+struct SnapshotDelta {
+    keyframe: u64,
+    pc: usize,
+    registers: Registers,
+    keyboard: Keypad,
+    random: StdRng,
+    callstack: CallStack,
+    display: DisplayWait,
+    fault: Option<CpuError>,
+    memory: ByteDelta,
+    graphics: ByteDelta,
+}
+
+impl SnapshotDelta {
+    fn between(keyframe: u64, base: &Snapshot, current: &Snapshot) -> Self {
+        SnapshotDelta {
+            keyframe,
+            pc: current.pc,
+            registers: current.registers,
+            keyboard: current.keyboard,
+            random: current.random.clone(),
+            callstack: current.memory.callstack(),
+            display: current.display,
+            fault: current.fault,
+            memory: ByteDelta::between(base.memory.bytes(), current.memory.bytes()),
+            graphics: ByteDelta::between(base.graphics.bytes(), current.graphics.bytes()),
+        }
+    }
+
+    fn apply(&self, base: &Snapshot) -> Snapshot {
+        let mut snapshot = base.clone();
+        snapshot.pc = self.pc;
+        snapshot.registers = self.registers;
+        snapshot.keyboard = self.keyboard;
+        snapshot.random = self.random.clone();
+        snapshot.memory.set_callstack(self.callstack);
+        snapshot.display = self.display;
+        snapshot.fault = self.fault;
+        self.memory.apply(snapshot.memory.bytes_mut());
+        self.graphics.apply(snapshot.graphics.bytes_mut());
+        snapshot
+    }
+}
+
+enum Entry {
+    Key(Box<Snapshot>),
+    Delta(Box<SnapshotDelta>),
+}
+
+struct Saved {
+    position: u64,
+    entry: Entry,
+}
+
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    saved: VecDeque<Saved>,
+    first_saved: u64,
+    ops: VecDeque<Op>,
+    first_op: u64,
+    ticks_since_save: u64,
+}
+
+impl History {
+    /// Keeps up to `capacity` snapshots, taken every `interval` instructions.
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        History {
+            interval: interval.max(1),
+            capacity: capacity.max(KEYFRAME_INTERVAL * 2),
+            saved: VecDeque::new(),
+            first_saved: 0,
+            ops: VecDeque::new(),
+            first_op: 0,
+            ticks_since_save: 0,
+        }
+    }
+
+    pub fn tick(&mut self, cpu: &mut Cpu) {
+        if self.saved.is_empty() || self.ticks_since_save >= self.interval {
+            self.save(cpu);
+        }
+        cpu.tick();
+        self.ops.push_back(Op::Tick);
+        self.ticks_since_save += 1;
+    }
+
+    pub fn tick_timers(&mut self, cpu: &mut Cpu) {
+        cpu.tick_timers();
+        self.ops.push_back(Op::Timers);
+    }
+
+    pub fn set_keypad(&mut self, cpu: &mut Cpu, keypad: Keypad) {
+        if *cpu.keyboard() != keypad {
+            *cpu.keyboard_mut() = keypad;
+            self.ops.push_back(Op::Input(keypad));
+        }
+    }
+
+    /// Instructions executed since the timers last ticked.
+    pub fn ticks_in_frame(&self) -> usize {
+        self.ops
+            .iter()
+            .rev()
+            .take_while(|op| !matches!(op, Op::Timers))
+            .filter(|op| matches!(op, Op::Tick))
+            .count()
+    }
+
+    /// Undoes the most recent instruction. Returns false if it is no longer
+    /// in the history.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        match self.ops.iter().rposition(|op| matches!(op, Op::Tick)) {
+            Some(i) => self.rewind_to(cpu, self.first_op + i as u64),
+            None => false,
+        }
+    }
+
+    /// Returns to the end of the frame `frames` frames ago, or as far back as
+    /// the history reaches. Returns false if nothing could be undone.
+    pub fn rewind_frames(&mut self, cpu: &mut Cpu, frames: usize) -> bool {
+        let target = self
+            .ops
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, op)| matches!(op, Op::Timers))
+            .nth(frames)
+            .map(|(i, _)| self.first_op + i as u64 + 1);
+        match (target, self.saved.front()) {
+            (Some(target), _) => self.rewind_to(cpu, target),
+            (None, Some(oldest)) => {
+                let oldest = oldest.position;
+                oldest < self.position() && self.rewind_to(cpu, oldest)
+            }
+            (None, None) => false,
+        }
+    }
+
+    fn position(&self) -> u64 {
+        self.first_op + self.ops.len() as u64
+    }
+
+    fn rewind_to(&mut self, cpu: &mut Cpu, target: u64) -> bool {
+        let index = match self.saved.iter().rposition(|s| s.position <= target) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.saved.truncate(index + 1);
+        let from = self.saved[index].position;
+        cpu.restore(&self.snapshot_at(index));
+
+        let start = (from - self.first_op) as usize;
+        let end = (target - self.first_op) as usize;
+        self.ops.truncate(end);
+        self.ticks_since_save = 0;
+        // The replayed instructions were traced when they first ran.
+        let tracer = cpu.take_tracer();
+        for &op in self.ops.range(start..) {
+            match op {
+                Op::Tick => {
+                    cpu.tick();
+                    self.ticks_since_save += 1;
+                }
+                Op::Timers => cpu.tick_timers(),
+                Op::Input(keypad) => *cpu.keyboard_mut() = keypad,
+            }
+        }
+        if let Some(tracer) = tracer {
+            cpu.resume_tracer(tracer);
+        }
+        true
+    }
+
+    fn save(&mut self, cpu: &Cpu) {
+        let snapshot = cpu.snapshot();
+        let keyframe = self
+            .saved
+            .iter()
+            .rposition(|s| matches!(s.entry, Entry::Key(_)))
+            .filter(|&i| self.saved.len() - i < KEYFRAME_INTERVAL);
+        let entry = match keyframe {
+            Some(i) => match &self.saved[i].entry {
+                Entry::Key(base) => Entry::Delta(Box::new(SnapshotDelta::between(
+                    self.first_saved + i as u64,
+                    base,
+                    &snapshot,
+                ))),
+                Entry::Delta(_) => unreachable!(),
+            },
+            None => Entry::Key(Box::new(snapshot)),
+        };
+        self.saved.push_back(Saved {
+            position: self.position(),
+            entry,
+        });
+        self.ticks_since_save = 0;
+
+        if self.saved.len() > self.capacity {
+            self.drop_oldest_keyframe();
+        }
+    }
+
+    fn drop_oldest_keyframe(&mut self) {
+        loop {
+            self.saved.pop_front();
+            self.first_saved += 1;
+            match self.saved.front() {
+                Some(Saved {
+                    entry: Entry::Key(_),
+                    ..
+                })
+                | None => break,
+                _ => {}
+            }
+        }
+        if let Some(oldest) = self.saved.front() {
+            let drop = (oldest.position - self.first_op) as usize;
+            self.ops.drain(..drop);
+            self.first_op = oldest.position;
+        }
+    }
+
+    fn snapshot_at(&self, index: usize) -> Snapshot {
+        match &self.saved[index].entry {
+            Entry::Key(snapshot) => (**snapshot).clone(),
+            Entry::Delta(delta) => {
+                let base = (delta.keyframe - self.first_saved) as usize;
+                match &self.saved[base].entry {
+                    Entry::Key(snapshot) => delta.apply(snapshot),
+                    Entry::Delta(_) => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::ChipKey;
+    use crate::trace::{TraceRecord, Tracer};
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Counts V0 up, drawing it and storing it to memory, until a key is held.
+    const COUNTER: &[u8] = &[
+        0x70, 0x01, // ADD V0, 0x01
+        0xF0, 0x29, // LD F, V0
+        0xD1, 0x15, // DRW V1, V1, 5
+        0xA3, 0x00, // LD I, 0x300
+        0xF0, 0x55, // LD [I], V0
+        0xE2, 0xA1, // SKNP V2
+        0x12, 0x0C, // JP 0x20C
+        0x12, 0x00, // JP 0x200
+    ];
+
+    fn run(history: &mut History, cpu: &mut Cpu, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..5 {
+                history.tick(cpu);
+            }
+            history.tick_timers(cpu);
+        }
+    }
+
+    fn state(cpu: &Cpu) -> (usize, Vec<u8>, Vec<u8>) {
+        (
+            cpu.pc(),
+            cpu.registers().v.to_vec(),
+            cpu.graphics().read_buffer(0, 0, 8),
+        )
+    }
+
+    #[test]
+    fn step_back_undoes_one_instruction() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let mut history = History::new(64, 4);
+        run(&mut history, &mut cpu, 3);
+        history.tick(&mut cpu);
+        let before = state(&cpu);
+        history.tick(&mut cpu);
+
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(before, state(&cpu));
+        assert_eq!(1, history.ticks_in_frame());
+    }
+
+    #[test]
+    fn rewind_frames_replays_inputs() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let mut history = History::new(64, 3);
+        run(&mut history, &mut cpu, 4);
+        let mut keys = *cpu.keyboard();
+        keys.press(ChipKey::CK_0);
+        history.set_keypad(&mut cpu, keys);
+        run(&mut history, &mut cpu, 2);
+        let before = state(&cpu);
+        run(&mut history, &mut cpu, 3);
+
+        assert!(history.rewind_frames(&mut cpu, 3));
+        assert_eq!(before, state(&cpu));
+        assert_eq!(true, cpu.keyboard().is_pressed(ChipKey::CK_0));
+        assert_eq!(0, history.ticks_in_frame());
+    }
+
+    #[test]
+    fn rewind_stops_at_oldest_snapshot() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let mut history = History::new(KEYFRAME_INTERVAL * 2, 1);
+        run(&mut history, &mut cpu, 40);
+        assert!(history.rewind_frames(&mut cpu, 1000));
+        assert!(!history.rewind_frames(&mut cpu, 1));
+        assert!(history.saved.len() <= KEYFRAME_INTERVAL * 2);
+    }
+
+    #[test]
+    fn rewinding_onto_a_delta_keeps_the_fault() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0x70, 0x01, 0x00, 0xFF]);
+        let mut history = History::new(64, 1);
+        for _ in 0..4 {
+            history.tick(&mut cpu);
+        }
+        assert!(history.step_back(&mut cpu));
+        assert!(matches!(
+            history.saved.back().unwrap().entry,
+            Entry::Delta(_)
+        ));
+        assert_eq!(Some(CpuError::UnsupportedInstruction(0x00FF)), cpu.fault());
+        assert_eq!(0x202, cpu.pc());
+    }
+
+    #[test]
+    fn replays_are_not_traced_again() {
+        struct Steps(Rc<RefCell<Vec<u64>>>);
+        impl Tracer for Steps {
+            fn trace(&mut self, record: &TraceRecord) {
+                self.0.borrow_mut().push(record.step);
+            }
+        }
+
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        cpu.set_tracer(Box::new(Steps(steps.clone())));
+        let mut history = History::new(64, 4);
+        run(&mut history, &mut cpu, 2);
+        assert!(history.step_back(&mut cpu));
+        history.tick(&mut cpu);
+        assert_eq!((0..11).collect::<Vec<_>>(), *steps.borrow());
+    }
+
+    #[test]
+    fn delta_snapshots_match_full_snapshots() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let base = cpu.snapshot();
+        for _ in 0..20 {
+            cpu.tick();
+        }
+        let current = cpu.snapshot();
+        let delta = SnapshotDelta::between(0, &base, &current);
+        assert!(delta.memory.0.len() < 8);
+
+        let mut restored = Cpu::default();
+        restored.restore(&delta.apply(&base));
+        assert_eq!(state(&cpu), state(&restored));
+        assert_eq!(cpu.graphics().bytes(), restored.graphics().bytes());
+    }
+}
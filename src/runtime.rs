@@ -0,0 +1,308 @@
+//! Drives a `Cpu` in real time.
+//!
+//! The host calls [`Runtime::advance`] with however much time has passed,
+//! and the runtime runs as many 60 Hz frames as are due. Each frame asks the
+//! host for input, executes a frame's share of instructions, ticks the timers
+//! and hands the host the screen and sound state. A frame's share is either a
+//! fixed number of instructions or, with [`Timing::Vip`], as many as fit into
+//! the COSMAC VIP's machine cycles for a frame.
+
+use crate::cpu::Cpu;
+use crate::graphics::Graphics;
+use crate::keypad::Keypad;
+use crate::timing::{Timing, VipClock};
+use core::time::Duration;
+use rand::rngs::StdRng;
+use rand::RngCore;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+/// The speed every frontend starts at unless a ROM or the user sets one.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = DEFAULT_INSTRUCTIONS_PER_FRAME * FRAMES_PER_SECOND;
+/// Frames of backlog kept at normal speed when the host falls behind. Any
+/// more are dropped rather than run in a burst.
+pub const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// What the runtime needs from the program embedding it. Every method has a
+/// default that does nothing.
+pub trait Host {
+    /// Updates the keypad before a frame runs.
+    fn input(&mut self, _keypad: &mut Keypad) {}
+
+    /// Receives the screen at the end of every frame.
+    fn frame(&mut self, _graphics: &Graphics) {}
+
+    /// Receives whether the buzzer sounds during the next frame.
+    fn sound(&mut self, _playing: bool) {}
+}
+
+pub struct Runtime<R = StdRng> {
+    cpu: Cpu<R>,
+    instructions_per_second: u32,
+    speed: u32,
+    paused: bool,
+    owed: Duration,
+    // Instructions owed, in sixtieths, so rates that are not a multiple of
+    // 60 stay exact over a second.
+    credit: u32,
+    timing: Timing,
+    clock: VipClock,
+}
+
+impl<R: RngCore> Runtime<R> {
+    pub fn new(cpu: Cpu<R>) -> Self {
+        Runtime {
+            cpu,
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            speed: 1,
+            paused: false,
+            owed: Duration::ZERO,
+            credit: 0,
+            timing: Timing::Fixed,
+            clock: VipClock::new(),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu<R> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<R> {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> Cpu<R> {
+        self.cpu
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+        self.credit = 0;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Chooses how many instructions make up a frame. With `Timing::Vip` the
+    /// instructions per second setting is ignored.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.clock = VipClock::new();
+    }
+
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+    /// Runs `speed` frames for every frame of real time, for fast-forward.
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.max(1);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops `advance` from running frames. Time passing while paused is
+    /// not made up afterwards.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.owed = Duration::ZERO;
+    }
+
+    /// Runs the frames due after `elapsed` more time, returning how many ran.
+    pub fn advance<H: Host>(&mut self, elapsed: Duration, host: &mut H) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        let max_owed = FRAME_DURATION * MAX_CATCH_UP_FRAMES * self.speed;
+        self.owed = (self.owed + elapsed * self.speed).min(max_owed);
+        let mut frames = 0;
+        while self.owed >= FRAME_DURATION {
+            self.owed -= FRAME_DURATION;
+            self.step_frame(host);
+            frames += 1;
+        }
+        frames
+    }
+
+    /// Runs exactly one frame, even while paused.
+    pub fn step_frame<H: Host>(&mut self, host: &mut H) {
+        host.input(self.cpu.keyboard_mut());
+        match self.timing {
+            Timing::Fixed => {
+                self.credit += self.instructions_per_second;
+                for _ in 0..self.credit / FRAMES_PER_SECOND {
+                    self.cpu.tick();
+                }
+                self.credit %= FRAMES_PER_SECOND;
+            }
+            Timing::Vip => self.clock.run_frame(&mut self.cpu, |cpu| cpu.tick()),
+        }
+        self.cpu.tick_timers();
+        host.frame(self.cpu.graphics());
+        host.sound(self.cpu.is_sound_playing());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::ChipKey;
+    use crate::quirks::Quirks;
+    use crate::timing::VIP_INSTRUCTION_CYCLES;
+    use pretty_assertions::assert_eq;
+
+    // Counts passes through the loop in V0, three instructions each while no
+    // key is held. Holding key 0 sets the sound timer to V2.
+    const COUNTER: &[u8] = &[
+        0x70, 0x01, // ADD V0, 0x01
+        0xE1, 0xA1, // SKNP V1
+        0xF2, 0x18, // LD ST, V2
+        0x12, 0x00, // JP 0x200
+    ];
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: usize,
+        sound: Vec<bool>,
+        hold: Option<ChipKey>,
+    }
+
+    impl Host for Recorder {
+        fn input(&mut self, keypad: &mut Keypad) {
+            match self.hold {
+                Some(key) => keypad.press(key),
+                None => *keypad = Keypad::new(),
+            }
+        }
+
+        fn frame(&mut self, _graphics: &Graphics) {
+            self.frames += 1;
+        }
+
+        fn sound(&mut self, playing: bool) {
+            self.sound.push(playing);
+        }
+    }
+
+    fn runtime(instructions_per_second: u32) -> Runtime {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(COUNTER);
+        let mut runtime = Runtime::new(cpu);
+        runtime.set_instructions_per_second(instructions_per_second);
+        runtime
+    }
+
+    fn loops(runtime: &Runtime) -> u8 {
+        runtime.cpu().registers().v[0x0]
+    }
+
+    #[test]
+    fn advance_runs_due_frames() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        assert_eq!(0, runtime.advance(FRAME_DURATION / 2, &mut host));
+        assert_eq!(2, runtime.advance(FRAME_DURATION * 3 / 2, &mut host));
+        assert_eq!(2, host.frames);
+        assert_eq!(2, loops(&runtime));
+    }
+
+    #[test]
+    fn fractional_rates_even_out() {
+        let mut runtime = runtime(90);
+        let mut host = Recorder::default();
+        for _ in 0..8 {
+            runtime.step_frame(&mut host);
+        }
+        // 90 per second is 1.5 per frame, so 12 instructions in 8 frames.
+        assert_eq!(4, loops(&runtime));
+    }
+
+    #[test]
+    fn backlog_is_capped() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        let frames = runtime.advance(Duration::from_secs(10), &mut host);
+        assert_eq!(MAX_CATCH_UP_FRAMES, frames);
+    }
+
+    #[test]
+    fn speed_multiplies_frames() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.set_speed(3);
+        assert_eq!(6, runtime.advance(FRAME_DURATION * 2, &mut host));
+    }
+
+    #[test]
+    fn paused_runtime_only_steps_frames() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.set_paused(true);
+        assert_eq!(0, runtime.advance(FRAME_DURATION * 3, &mut host));
+        runtime.step_frame(&mut host);
+        assert_eq!(1, host.frames);
+        runtime.set_paused(false);
+        assert_eq!(1, runtime.advance(FRAME_DURATION, &mut host));
+    }
+
+    #[test]
+    fn vip_timing_fits_cycles_into_a_frame() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.set_timing(Timing::Vip);
+        runtime.step_frame(&mut host);
+        // ADD, a taken SKNP and JP take 50, 58 and 52 cycles.
+        assert_eq!(VIP_INSTRUCTION_CYCLES / 160 + 1, loops(&runtime) as u32);
+    }
+
+    #[test]
+    fn vip_timing_waits_for_next_frame_after_drawing() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.set_timing(Timing::Vip);
+        runtime.cpu_mut().memory_mut().store(0x202, &[0xD1, 0x11]);
+        for _ in 0..3 {
+            runtime.step_frame(&mut host);
+        }
+        assert_eq!(3, loops(&runtime));
+    }
+
+    #[test]
+    fn vip_timing_with_display_wait_draws_at_frame_start() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.set_timing(Timing::Vip);
+        runtime.cpu_mut().set_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+        runtime.cpu_mut().memory_mut().store(0x202, &[0xD1, 0x11]);
+        for _ in 0..3 {
+            runtime.step_frame(&mut host);
+        }
+        // The first frame ends waiting; each later one draws, loops and
+        // waits again.
+        assert_eq!(3, loops(&runtime));
+    }
+
+    #[test]
+    fn host_supplies_input_and_hears_sound() {
+        let mut runtime = runtime(180);
+        let mut host = Recorder::default();
+        runtime.cpu_mut().memory_mut().store(0x200, &[0x62, 0x05]);
+        runtime.step_frame(&mut host);
+        host.hold = Some(ChipKey::CK_0);
+        runtime.step_frame(&mut host);
+        host.hold = None;
+        runtime.step_frame(&mut host);
+        assert_eq!(vec![false, true, true], host.sound);
+    }
+}
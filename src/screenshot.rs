@@ -0,0 +1,444 @@
+//! Exports the screen as PBM or PNG images.
+//!
+//! Plain PBM (`P1`) images are text, one row per line, which makes them
+//! handy as test fixtures. PNG images can be scaled up and drawn in any two
+//! colours. Both can be read back, so either can be a fixture.
+
+use crate::graphics::{Graphics, GRAPHICS_HEIGHT, GRAPHICS_WIDTH};
+use std::path::Path;
+use std::{fmt, fs, io};
+
+/// Colours of dark and lit pixels, as RGB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub off: [u8; 3],
+    pub on: [u8; 3],
+}
+
+/// How screenshots are drawn: each CHIP-8 pixel becomes a `scale` by `scale`
+/// square in the palette's colours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    pub(crate) scale: usize,
+    pub(crate) palette: Palette,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PbmError {
+    NotPlainPbm,
+    WrongSize { width: usize, height: usize },
+    BadPixel(String),
+    MissingPixels,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PngError {
+    Decode(String),
+    WrongSize {
+        width: usize,
+        height: usize,
+    },
+    /// More than a dark and a lit colour.
+    TooManyColours,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file name ends in neither `.pbm` nor `.png`.
+    UnknownFormat,
+    Pbm(PbmError),
+    Png(PngError),
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file name ends in neither `.pbm` nor `.png`.
+    UnknownFormat,
+    Io(io::Error),
+}
+
+impl Palette {
+    pub const MONOCHROME: Palette = Palette {
+        off: [0x00, 0x00, 0x00],
+        on: [0xFF, 0xFF, 0xFF],
+    };
+
+    /// Reads `off:on` as two hex colours, e.g. `000000:33ff66`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (off, on) = text.split_once(':')?;
+        Some(Palette {
+            off: parse_colour(off)?,
+            on: parse_colour(on)?,
+        })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::MONOCHROME
+    }
+}
+
+fn parse_colour(text: &str) -> Option<[u8; 3]> {
+    let text = text.strip_prefix('#').unwrap_or(text);
+    if text.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(text, 16).ok()?;
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+impl Screenshot {
+    pub fn new() -> Self {
+        Screenshot {
+            scale: 1,
+            palette: Palette::MONOCHROME,
+        }
+    }
+
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// Sets the colours used for PNG images. PBM images are always black
+    /// and white.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        GRAPHICS_WIDTH * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        GRAPHICS_HEIGHT * self.scale
+    }
+
+    /// Writes a plain PBM image, one text row per pixel row.
+    pub fn pbm(&self, graphics: &Graphics) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width(), self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let lit = graphics.is_pixel_set(x / self.scale, y / self.scale);
+                pbm.push(if lit { '1' } else { '0' });
+            }
+            pbm.push('\n');
+        }
+        pbm
+    }
+
+    /// The scaled image as one byte per pixel, 1 for lit and 0 for dark.
+    pub(crate) fn indexed(&self, graphics: &Graphics) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.width() * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let lit = graphics.is_pixel_set(x / self.scale, y / self.scale);
+                pixels.push(lit as u8);
+            }
+        }
+        pixels
+    }
+
+    pub fn png(&self, graphics: &Graphics) -> Vec<u8> {
+        let pixels: Vec<u8> = self
+            .indexed(graphics)
+            .into_iter()
+            .flat_map(|lit| {
+                if lit == 1 {
+                    self.palette.on
+                } else {
+                    self.palette.off
+                }
+            })
+            .collect();
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width() as u32, self.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Encoding into memory only fails on a bad header, and the header
+        // always matches the pixels.
+        let mut writer = encoder.write_header().expect("valid PNG header");
+        writer
+            .write_image_data(&pixels)
+            .expect("image matches header");
+        writer.finish().expect("image written to memory");
+        png
+    }
+
+    /// Saves `graphics` to `path`, choosing PBM or PNG by its extension.
+    pub fn save<P: AsRef<Path>>(&self, graphics: &Graphics, path: P) -> Result<(), SaveError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str());
+        let bytes = match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("pbm") => self.pbm(graphics).into_bytes(),
+            Some("png") => self.png(graphics),
+            _ => return Err(SaveError::UnknownFormat),
+        };
+        fs::write(path, bytes).map_err(SaveError::Io)
+    }
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Screenshot::new()
+    }
+}
+
+/// Writes the screen as an unscaled plain PBM image.
+pub fn to_pbm(graphics: &Graphics) -> String {
+    Screenshot::new().pbm(graphics)
+}
+
+/// Reads an unscaled plain PBM image of the screen. Pixels may be separated
+/// by whitespace or not, and `#` starts a comment.
+pub fn parse_pbm(text: &str) -> Result<Graphics, PbmError> {
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(str::split_whitespace);
+    if tokens.next() != Some("P1") {
+        return Err(PbmError::NotPlainPbm);
+    }
+    let mut size = || tokens.next().and_then(|n| n.parse::<usize>().ok());
+    let (width, height) = match (size(), size()) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Err(PbmError::NotPlainPbm),
+    };
+    if (width, height) != (GRAPHICS_WIDTH, GRAPHICS_HEIGHT) {
+        return Err(PbmError::WrongSize { width, height });
+    }
+
+    let mut graphics = Graphics::new();
+    let mut pixels = tokens.flat_map(str::chars);
+    for y in 0..GRAPHICS_HEIGHT {
+        for x in 0..GRAPHICS_WIDTH {
+            match pixels.next() {
+                Some('0') => {}
+                Some('1') => graphics.set_pixel(x, y, true),
+                Some(c) => return Err(PbmError::BadPixel(c.to_string())),
+                None => return Err(PbmError::MissingPixels),
+            }
+        }
+    }
+    Ok(graphics)
+}
+
+/// Reads a PNG image of the screen as written by [`Screenshot::png`], at any
+/// scale and in any two colours. The brighter colour is lit; an image in one
+/// colour is taken to be blank.
+pub fn parse_png(bytes: &[u8]) -> Result<Graphics, PngError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| PngError::Decode(e.to_string()))?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .map_err(|e| PngError::Decode(e.to_string()))?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let scale = width / GRAPHICS_WIDTH;
+    if scale == 0 || (width, height) != (GRAPHICS_WIDTH * scale, GRAPHICS_HEIGHT * scale) {
+        return Err(PngError::WrongSize { width, height });
+    }
+
+    let samples = info.color_type.samples();
+    let colour = |x: usize, y: usize| {
+        let i = ((y * scale + scale / 2) * width + x * scale + scale / 2) * samples;
+        match info.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                [pixels[i], pixels[i], pixels[i]]
+            }
+            _ => [pixels[i], pixels[i + 1], pixels[i + 2]],
+        }
+    };
+    let mut colours: Vec<[u8; 3]> = Vec::new();
+    for y in 0..GRAPHICS_HEIGHT {
+        for x in 0..GRAPHICS_WIDTH {
+            let colour = colour(x, y);
+            if !colours.contains(&colour) {
+                if colours.len() == 2 {
+                    return Err(PngError::TooManyColours);
+                }
+                colours.push(colour);
+            }
+        }
+    }
+    let brightness = |colour: &[u8; 3]| colour.iter().map(|&c| c as u32).sum::<u32>();
+    let on = match colours[..] {
+        [a, b] if brightness(&a) > brightness(&b) => Some(a),
+        [_, b] => Some(b),
+        _ => None,
+    };
+
+    let mut graphics = Graphics::new();
+    for y in 0..GRAPHICS_HEIGHT {
+        for x in 0..GRAPHICS_WIDTH {
+            if Some(colour(x, y)) == on {
+                graphics.set_pixel(x, y, true);
+            }
+        }
+    }
+    Ok(graphics)
+}
+
+/// Reads a PBM or PNG image of the screen, choosing by the extension of
+/// `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Graphics, LoadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str());
+    match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("pbm") => {
+            let text = fs::read_to_string(path).map_err(LoadError::Io)?;
+            parse_pbm(&text).map_err(LoadError::Pbm)
+        }
+        Some("png") => {
+            let bytes = fs::read(path).map_err(LoadError::Io)?;
+            parse_png(&bytes).map_err(LoadError::Png)
+        }
+        _ => Err(LoadError::UnknownFormat),
+    }
+}
+
+impl fmt::Display for PbmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PbmError::NotPlainPbm => write!(f, "not a plain (P1) PBM image"),
+            PbmError::WrongSize { width, height } => write!(
+                f,
+                "image is {}x{}, expected {}x{}",
+                width, height, GRAPHICS_WIDTH, GRAPHICS_HEIGHT
+            ),
+            PbmError::BadPixel(c) => write!(f, "invalid pixel `{}`", c),
+            PbmError::MissingPixels => write!(f, "image ends early"),
+        }
+    }
+}
+
+impl std::error::Error for PbmError {}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PngError::Decode(message) => write!(f, "{}", message),
+            PngError::WrongSize { width, height } => write!(
+                f,
+                "image is {}x{}, expected a multiple of {}x{}",
+                width, height, GRAPHICS_WIDTH, GRAPHICS_HEIGHT
+            ),
+            PngError::TooManyColours => write!(f, "image has more than two colours"),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::UnknownFormat => write!(f, "file name must end in .pbm or .png"),
+            LoadError::Pbm(e) => write!(f, "{}", e),
+            LoadError::Png(e) => write!(f, "{}", e),
+            LoadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::UnknownFormat => write!(f, "file name must end in .pbm or .png"),
+            SaveError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pbm_round_trip() {
+        let mut graphics = Graphics::new();
+        graphics.draw_with_collision(62, 30, &[0xFF, 0x81]);
+        let parsed = parse_pbm(&to_pbm(&graphics)).unwrap();
+        assert_eq!(graphics.bytes(), parsed.bytes());
+    }
+
+    #[test]
+    fn parse_pbm_rejects_other_sizes() {
+        assert_eq!(
+            Err(PbmError::WrongSize {
+                width: 2,
+                height: 1
+            }),
+            parse_pbm("P1\n# tiny\n2 1\n0 1\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(PbmError::NotPlainPbm),
+            parse_pbm("P4\n64 32\n").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn scaled_pbm_repeats_pixels() {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(1, 0, true);
+        let pbm = Screenshot::new().scale(2).pbm(&graphics);
+        let mut lines = pbm.lines();
+        assert_eq!(Some("P1"), lines.next());
+        assert_eq!(Some("128 64"), lines.next());
+        assert!(lines.next().unwrap().starts_with("00110"));
+        assert!(lines.next().unwrap().starts_with("00110"));
+        assert!(lines.next().unwrap().starts_with("00000"));
+    }
+
+    #[test]
+    fn png_uses_scale_and_palette() {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(1, 0, true);
+        let palette = Palette::parse("102030:#33ff66").unwrap();
+        let png = Screenshot::new().scale(3).palette(palette).png(&graphics);
+
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((192, 96), (info.width, info.height));
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 192 + x) * 3;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+        assert_eq!([0x10, 0x20, 0x30], pixel(2, 0));
+        assert_eq!([0x33, 0xFF, 0x66], pixel(3, 2));
+        assert_eq!([0x33, 0xFF, 0x66], pixel(5, 0));
+        assert_eq!([0x10, 0x20, 0x30], pixel(6, 0));
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut graphics = Graphics::new();
+        graphics.draw_with_collision(62, 30, &[0xFF, 0x81]);
+        let palette = Palette::parse("102030:33ff66").unwrap();
+        let png = Screenshot::new().scale(3).palette(palette).png(&graphics);
+        assert_eq!(graphics.bytes(), parse_png(&png).unwrap().bytes());
+
+        let blank = Screenshot::new().png(&Graphics::new());
+        assert_eq!(Graphics::new().bytes(), parse_png(&blank).unwrap().bytes());
+    }
+
+    #[test]
+    fn palette_needs_two_colours() {
+        assert_eq!(None, Palette::parse("000000"));
+        assert_eq!(None, Palette::parse("000000:fff"));
+    }
+}
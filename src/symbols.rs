@@ -0,0 +1,278 @@
+//! Names for addresses, read from symbol files.
+//!
+//! A symbol file has one label per line, as either `label = address` or
+//! `address label`, so both hand-written files and assembler listings such as
+//! `0x208 draw_ball` are accepted. Addresses are hex, with or without a `0x`
+//! or `$` prefix. Blank lines and anything after `#` or `;` are ignored:
+//!
+//! ```text
+//! ; pong.sym
+//! main = 0x200
+//! 0x2C4 draw_paddles
+//! ```
+
+use crate::instruction::Instruction;
+use crate::memory::CHIP8_RAM;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fmt, fs};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    addresses: BTreeMap<String, usize>,
+    // The first label given for each address.
+    labels: BTreeMap<usize, String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SymbolError {
+    /// A line that is not a label and an address, by line number.
+    BadLine(usize, String),
+    /// A label given two different addresses.
+    Duplicate(String),
+    Io(String),
+}
+
+/// Shows an instruction with the addresses it uses replaced by labels.
+pub struct Labelled<'a> {
+    instruction: Instruction,
+    symbols: &'a SymbolMap,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        SymbolMap {
+            addresses: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = SymbolMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = || SymbolError::BadLine(number + 1, line.to_string());
+            let (label, address) = match line.split_once('=') {
+                Some((label, address)) => (label.trim(), parse_hex(address.trim())),
+                None => match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    [address, label] => (label, parse_hex(address)),
+                    _ => return Err(bad()),
+                },
+            };
+            let address = address.ok_or_else(bad)?;
+            if !is_label(label) {
+                return Err(bad());
+            }
+            if symbols
+                .address_of(label)
+                .is_some_and(|known| known != address)
+            {
+                return Err(SymbolError::Duplicate(label.to_string()));
+            }
+            symbols.insert(label, address);
+        }
+        Ok(symbols)
+    }
+
+    /// Reads a symbol file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SymbolError> {
+        let text = fs::read_to_string(path).map_err(|e| SymbolError::Io(e.to_string()))?;
+        SymbolMap::parse(&text)
+    }
+
+    /// Names `address`. An address keeps the first label it was given.
+    pub fn insert(&mut self, label: &str, address: usize) {
+        self.addresses.insert(label.to_string(), address);
+        self.labels
+            .entry(address)
+            .or_insert_with(|| label.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<usize> {
+        self.addresses.get(label).copied()
+    }
+
+    pub fn label_at(&self, address: usize) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Labels with their addresses, by address.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.labels
+            .iter()
+            .map(|(&address, label)| (address, label.as_str()))
+    }
+
+    /// `address` as the nearest label at or before it plus an offset, such as
+    /// `loop+4`, or in hex if no label comes before it.
+    pub fn describe(&self, address: usize) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&start, label)) if start == address => label.clone(),
+            Some((&start, label)) => format!("{}+{}", label, address - start),
+            None => format!("{:03X}", address),
+        }
+    }
+
+    /// `address` in hex, followed by where it is among the labels if there
+    /// are any, such as `20A (loop+4)`.
+    pub fn locate(&self, address: usize) -> String {
+        if self.is_empty() {
+            return format!("{:03X}", address);
+        }
+        format!("{:03X} ({})", address, self.describe(address))
+    }
+
+    /// Reads an address given in hex or as a label, optionally with a
+    /// decimal offset as in `loop+4`.
+    pub fn resolve(&self, text: &str) -> Option<usize> {
+        let address = match text.split_once('+') {
+            Some((label, offset)) => self.address_of(label)? + offset.parse::<usize>().ok()?,
+            None => self.address_of(text).or_else(|| parse_hex(text))?,
+        };
+        Some(address).filter(|&address| address < CHIP8_RAM)
+    }
+
+    pub fn labelled(&self, instruction: Instruction) -> Labelled<'_> {
+        Labelled {
+            instruction,
+            symbols: self,
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+impl fmt::Display for Labelled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        let (mnemonic, address) = match self.instruction {
+            Sys(nnn) => ("SYS", nnn),
+            Jp(nnn) => ("JP", nnn),
+            Call(nnn) => ("CALL", nnn),
+            LdI(nnn) => ("LD I,", nnn),
+            JpV0(nnn) => ("JP V0,", nnn),
+            instruction => return write!(f, "{}", instruction),
+        };
+        match self.symbols.label_at(address) {
+            Some(label) => write!(f, "{} {}", mnemonic, label),
+            None => write!(f, "{}", self.instruction),
+        }
+    }
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::BadLine(number, line) => {
+                write!(
+                    f,
+                    "line {}: expected a label and an address: {}",
+                    number, line
+                )
+            }
+            SymbolError::Duplicate(label) => write!(f, "{} has two addresses", label),
+            SymbolError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SYMBOLS: &str = "
+        ; pong.sym
+        main = 0x200
+        0x2C4 draw_paddles   # from the assembler
+        $2D0 score
+        start = 200
+    ";
+
+    #[test]
+    fn parses_both_line_forms() {
+        let symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        assert_eq!(Some(0x2C4), symbols.address_of("draw_paddles"));
+        assert_eq!(Some("main"), symbols.label_at(0x200));
+        assert_eq!(Some(0x200), symbols.address_of("start"));
+        assert_eq!(
+            vec![(0x200, "main"), (0x2C4, "draw_paddles"), (0x2D0, "score")],
+            symbols.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(
+            Err(SymbolError::BadLine(2, "main 0x200 extra".to_string())),
+            SymbolMap::parse("\nmain 0x200 extra")
+        );
+        assert_eq!(
+            Err(SymbolError::BadLine(1, "2 = 0x200".to_string())),
+            SymbolMap::parse("2 = 0x200")
+        );
+        assert_eq!(
+            Err(SymbolError::Duplicate("main".to_string())),
+            SymbolMap::parse("main = 200\nmain = 202")
+        );
+    }
+
+    #[test]
+    fn resolves_and_describes_addresses() {
+        let symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        assert_eq!(Some(0x2C8), symbols.resolve("draw_paddles+4"));
+        assert_eq!(Some(0x2D0), symbols.resolve("score"));
+        assert_eq!(Some(0x123), symbols.resolve("0x123"));
+        assert_eq!(None, symbols.resolve("nowhere"));
+        assert_eq!(None, symbols.resolve("1000"));
+        assert_eq!("draw_paddles+6", symbols.describe(0x2CA));
+        assert_eq!("main", symbols.describe(0x200));
+        assert_eq!("050", symbols.describe(0x050));
+        assert_eq!("2CA (draw_paddles+6)", symbols.locate(0x2CA));
+        assert_eq!("2CA", SymbolMap::new().locate(0x2CA));
+    }
+
+    #[test]
+    fn labels_instruction_targets() {
+        let symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        assert_eq!(
+            "CALL draw_paddles",
+            symbols.labelled(Instruction::Call(0x2C4)).to_string()
+        );
+        assert_eq!(
+            "LD I, score",
+            symbols.labelled(Instruction::LdI(0x2D0)).to_string()
+        );
+        assert_eq!(
+            "JP 0x300",
+            symbols.labelled(Instruction::Jp(0x300)).to_string()
+        );
+        assert_eq!(
+            "LD V1, 0x02",
+            symbols.labelled(Instruction::LdByte(1, 2)).to_string()
+        );
+    }
+}
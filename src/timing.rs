@@ -0,0 +1,166 @@
+//! Instruction costs on the COSMAC VIP.
+//!
+//! The VIP's CDP1802 runs at 1.7609 MHz with eight clocks to a machine cycle,
+//! giving 3668 machine cycles per 60 Hz frame. Display DMA and the interrupt
+//! routine take about 1070 of those, leaving the rest to the interpreter.
+//! Costs are counted in machine cycles, including fetching and dispatching
+//! the instruction, and follow the structure of the VIP interpreter's code:
+//! fixed for most instructions, and growing with the work done for `Dxyn`,
+//! `Fx33`, `Fx55`, `Fx65` and `00E0`. They are close to, not exactly, what
+//! the hardware takes.
+
+use crate::cpu::Cpu;
+use crate::graphics::GRAPHICS_VBUFFER;
+use crate::instruction::Instruction;
+use rand::RngCore;
+
+/// How the runtime decides how many instructions fit into a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// A fixed number of instructions per second, whatever they do.
+    #[default]
+    Fixed,
+    /// VIP machine cycles per instruction, with drawing waiting for the
+    /// next frame like the VIP interpreter does.
+    Vip,
+}
+
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// Cycles per frame taken by display DMA and the interrupt routine.
+pub const VIP_FRAME_OVERHEAD: u32 = 1024 + 46;
+/// Cycles per frame left for running instructions.
+pub const VIP_INSTRUCTION_CYCLES: u32 = VIP_CYCLES_PER_FRAME - VIP_FRAME_OVERHEAD;
+
+/// Runs a frame's worth of VIP cycles at a time, carrying any overrun into
+/// the next frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VipClock {
+    overrun: u64,
+}
+
+const FETCH: u32 = 40;
+/// Extra cost of taking a skip.
+const SKIP: u32 = 4;
+/// Extra cost of `Bnnn` and `Fx1E` when the address moves into another page.
+const PAGE_CROSS: u32 = 2;
+
+/// The machine cycles `instruction` takes with the registers as they are
+/// before it runs. `skipped` says whether a conditional skip was taken.
+pub fn vip_cycles(instruction: Instruction, v: &[u8; 16], ri: usize, skipped: bool) -> u32 {
+    use Instruction::*;
+    let skip = if skipped { SKIP } else { 0 };
+    let page_cross = |base: usize, offset: usize| {
+        if (base & 0xF00) != ((base + offset) & 0xF00) {
+            PAGE_CROSS
+        } else {
+            0
+        }
+    };
+    FETCH
+        + match instruction {
+            Cls => 24 + 6 * GRAPHICS_VBUFFER as u32,
+            Ret => 10,
+            Sys(_) | Scu(_) | Jp(_) | LdI(_) => 12,
+            Call(_) => 26,
+            SeByte(..) | SneByte(..) => 10 + skip,
+            SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) => 14 + skip,
+            LdByte(..) => 6,
+            AddByte(..) | LdVxDt(_) | LdDtVx(_) | LdStVx(_) => 10,
+            LdReg(..) | Or(..) | And(..) | Xor(..) | AddReg(..) | Sub(..) | Shr(..) | Subn(..)
+            | Shl(..) => 44,
+            JpV0(nnn) => 22 + page_cross(nnn, v[0] as usize),
+            Rnd(..) => 36,
+            Drw(x, _, n) => {
+                let shift = v[x] as u32 % 8;
+                let straddle = if shift == 0 { 0 } else { 12 };
+                26 + n as u32 * (34 + 4 * shift + straddle)
+            }
+            LdVxK(_) => 18,
+            AddI(x) => 16 + page_cross(ri, v[x] as usize),
+            LdF(_) => 16,
+            LdB(x) => {
+                let vx = v[x] as u32;
+                80 + 16 * (vx / 100 + (vx / 10) % 10 + vx % 10)
+            }
+            LdIVx(x) | LdVxI(x) => 14 + 14 * (x as u32 + 1),
+            Scd(_) | Scr | Scl | Exit | Low | High | DrwHigh(..) | LdHf(_) | LdRVx(_)
+            | LdVxR(_) | SaveRange(..) | LoadRange(..) | LdILong | Plane(_) | Audio | Pitch(_)
+            | Unknown(_) => 0,
+        }
+}
+
+impl VipClock {
+    pub fn new() -> Self {
+        VipClock { overrun: 0 }
+    }
+
+    /// Calls `tick` until the frame's cycles are used up, the `Cpu` faults
+    /// or it waits for the display, since the VIP interpreter waits for the
+    /// next frame to draw. Without `Quirks::display_wait` the frame ends
+    /// after drawing instead. `tick` must execute one instruction.
+    pub fn run_frame<R, F>(&mut self, cpu: &mut Cpu<R>, mut tick: F)
+    where
+        R: RngCore,
+        F: FnMut(&mut Cpu<R>),
+    {
+        let available = VIP_INSTRUCTION_CYCLES as u64;
+        if self.overrun >= available {
+            self.overrun -= available;
+            return;
+        }
+        let end = cpu.cycles() + available - self.overrun;
+        while cpu.cycles() < end && cpu.fault().is_none() {
+            let pc = cpu.pc();
+            let instruction = cpu.read_word(pc).map(Instruction::decode);
+            tick(cpu);
+            // A sprite that has already waited for this frame keeps it going.
+            let drew_after_waiting = cpu.quirks().display_wait && cpu.pc() != pc;
+            if matches!(instruction, Ok(Instruction::Drw(..))) && !drew_after_waiting {
+                break;
+            }
+        }
+        self.overrun = cpu.cycles().saturating_sub(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn skips_cost_extra() {
+        let v = [0; 16];
+        let instruction = Instruction::decode(0x3000);
+        assert_eq!(
+            vip_cycles(instruction, &v, 0, false) + SKIP,
+            vip_cycles(instruction, &v, 0, true)
+        );
+    }
+
+    #[test]
+    fn unaligned_sprites_draw_slower() {
+        let mut v = [0; 16];
+        let drw = Instruction::decode(0xD015);
+        let aligned = vip_cycles(drw, &v, 0, false);
+        v[0] = 3;
+        assert!(vip_cycles(drw, &v, 0, false) > aligned);
+        assert!(vip_cycles(Instruction::decode(0xD01F), &v, 0, false) > aligned);
+    }
+
+    #[test]
+    fn bcd_cost_follows_digits() {
+        let mut v = [0; 16];
+        v[0] = 255;
+        assert_eq!(
+            FETCH + 80 + 16 * 12,
+            vip_cycles(Instruction::decode(0xF033), &v, 0, false)
+        );
+    }
+
+    #[test]
+    fn clear_takes_over_half_a_frame() {
+        let cls = vip_cycles(Instruction::Cls, &[0; 16], 0, false);
+        assert!(cls > VIP_INSTRUCTION_CYCLES / 2);
+    }
+}
@@ -0,0 +1,182 @@
+//! Per-instruction execution traces.
+//!
+//! Every executed instruction produces one line of text:
+//!
+//! ```text
+//! step=42 pc=0206 op=F033 i=0300 dt=00 st=00 v=7B000000000000000000000000000000 | [0300]=010203 | LD B, V0
+//! ```
+//!
+//! The first field group is the machine state: `step` counts instructions
+//! from when tracing began, `pc` and `op` are the address and opcode of the
+//! instruction that ran, and `i`, `dt`, `st` and `v` (V0 through VF) hold the
+//! values *after* it ran. All numbers except `step` are uppercase hex without
+//! a prefix, zero padded to their full width.
+//!
+//! The second group lists what the instruction changed: registers as
+//! `V0=75`, `I=0300`, `DT=3C` or `ST=3C`, and memory writes as the starting
+//! address in brackets followed by the bytes written. A lone `-` means
+//! nothing changed. The last group is the disassembly.
+
+use crate::cpu::Registers;
+use crate::instruction::Instruction;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub step: u64,
+    pub pc: usize,
+    pub opcode: u16,
+    pub before: Registers,
+    pub after: Registers,
+    pub writes: Vec<MemoryWrite>,
+}
+
+pub trait Tracer {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+/// Streams trace lines to any writer, such as a file.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(TraceWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        TraceWriter { out, error: None }
+    }
+
+    /// Returns the first error hit while writing, if any. Tracing stops
+    /// after an error so that a full disk does not halt emulation.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", record) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let after = &self.after;
+        write!(
+            f,
+            "step={} pc={:04X} op={:04X} i={:04X} dt={:02X} st={:02X} v=",
+            self.step, self.pc, self.opcode, after.ri, after.dt, after.st
+        )?;
+        for vx in after.v.iter() {
+            write!(f, "{:02X}", vx)?;
+        }
+
+        write!(f, " |")?;
+        let mut changed = false;
+        for (x, (old, new)) in self.before.v.iter().zip(after.v.iter()).enumerate() {
+            if old != new {
+                write!(f, " V{:X}={:02X}", x, new)?;
+                changed = true;
+            }
+        }
+        if self.before.ri != after.ri {
+            write!(f, " I={:04X}", after.ri)?;
+            changed = true;
+        }
+        if self.before.dt != after.dt {
+            write!(f, " DT={:02X}", after.dt)?;
+            changed = true;
+        }
+        if self.before.st != after.st {
+            write!(f, " ST={:02X}", after.st)?;
+            changed = true;
+        }
+        for write in self.writes.iter() {
+            write!(f, " [{:04X}]=", write.address)?;
+            for byte in write.bytes.iter() {
+                write!(f, "{:02X}", byte)?;
+            }
+            changed = true;
+        }
+        if !changed {
+            write!(f, " -")?;
+        }
+
+        write!(f, " | {}", Instruction::decode(self.opcode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn record(opcode: u16) -> TraceRecord {
+        let before = Registers {
+            v: [0; 16],
+            ri: 0x300,
+            dt: 0,
+            st: 0,
+        };
+        TraceRecord {
+            step: 42,
+            pc: 0x206,
+            opcode,
+            before,
+            after: before,
+            writes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn format_unchanged() {
+        assert_eq!(
+            "step=42 pc=0206 op=00E0 i=0300 dt=00 st=00 v=00000000000000000000000000000000 | - | CLS",
+            record(0x00E0).to_string()
+        );
+    }
+
+    #[test]
+    fn format_changes() {
+        let mut record = record(0xF033);
+        record.after.v[0x0] = 0x7B;
+        record.after.v[0xF] = 0x01;
+        record.after.ri = 0x301;
+        record.writes.push(MemoryWrite {
+            address: 0x300,
+            bytes: vec![1, 2, 3],
+        });
+        assert_eq!(
+            "step=42 pc=0206 op=F033 i=0301 dt=00 st=00 v=7B000000000000000000000000000001 \
+             | V0=7B VF=01 I=0301 [0300]=010203 | LD B, V0",
+            record.to_string()
+        );
+    }
+
+    #[test]
+    fn writer_emits_lines() {
+        let mut tracer = TraceWriter::new(Vec::new());
+        tracer.trace(&record(0x00E0));
+        tracer.trace(&record(0x00E0));
+        assert_eq!(2, String::from_utf8(tracer.out).unwrap().lines().count());
+    }
+}
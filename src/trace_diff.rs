@@ -0,0 +1,356 @@
+//! Compares an execution trace against one from another emulator.
+//!
+//! Besides the lines written by [`crate::trace`], this reads a looser
+//! reference format so that other emulators only need a small logging patch:
+//!
+//! * one executed instruction per line, blank lines and lines starting with
+//!   `#` are ignored;
+//! * whitespace separated `key=value` fields, with keys matched ignoring case
+//!   and everything after the first `|` ignored;
+//! * recognised keys are `step` (decimal), `pc`, `op`, `i`, `dt`, `st`, `v`
+//!   (all sixteen registers as 32 hex digits) and `v0` to `vf`. Other keys
+//!   are ignored, and hex values may carry a `0x` prefix;
+//! * registers hold their values after the instruction ran;
+//! * lines without a `step` are numbered from zero in file order.
+//!
+//! Only fields present in both traces are compared.
+
+use std::fmt;
+use std::io::{self, BufRead};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceLine {
+    pub step: u64,
+    pub pc: Option<usize>,
+    pub opcode: Option<u16>,
+    pub ri: Option<usize>,
+    pub dt: Option<u8>,
+    pub st: Option<u8>,
+    pub v: [Option<u8>; 16],
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum TraceParseError {
+    Io(io::Error),
+    InvalidField { line: usize, field: String },
+}
+
+/// The first step at which two traces disagree.
+pub struct Divergence<'a> {
+    pub step: u64,
+    /// Our line for the step, or `None` if our trace ended before it.
+    pub ours: Option<&'a TraceLine>,
+    /// Their line for the step, or `None` if their trace ended before it.
+    pub theirs: Option<&'a TraceLine>,
+    /// The differing fields, empty when one trace ended early.
+    pub fields: Vec<String>,
+    /// Our lines leading up to the divergence, oldest first.
+    pub context: &'a [TraceLine],
+    /// Our lines following the divergence.
+    pub ours_after: &'a [TraceLine],
+    /// Their lines following the divergence.
+    pub theirs_after: &'a [TraceLine],
+}
+
+impl TraceLine {
+    /// Parses a single line, numbering it `index` if it carries no step.
+    /// Returns `None` for blank and comment lines.
+    pub fn parse(line: &str, index: u64) -> Result<Option<Self>, String> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(None);
+        }
+        let mut parsed = TraceLine {
+            step: index,
+            text: trimmed.to_string(),
+            ..TraceLine::default()
+        };
+        let fields = trimmed.split('|').next().unwrap_or("");
+        for field in fields.split_whitespace() {
+            let (key, value) = match field.find('=') {
+                Some(at) => (field[..at].to_ascii_lowercase(), &field[at + 1..]),
+                None => continue,
+            };
+            let invalid = || field.to_string();
+            match key.as_str() {
+                "step" => parsed.step = value.parse().map_err(|_| invalid())?,
+                "pc" => parsed.pc = Some(parse_hex(value).ok_or_else(invalid)? as usize),
+                "op" => parsed.opcode = Some(parse_hex(value).ok_or_else(invalid)? as u16),
+                "i" => parsed.ri = Some(parse_hex(value).ok_or_else(invalid)? as usize),
+                "dt" => parsed.dt = Some(parse_byte(value).ok_or_else(invalid)?),
+                "st" => parsed.st = Some(parse_byte(value).ok_or_else(invalid)?),
+                "v" => {
+                    let digits = value.trim_start_matches("0x");
+                    if digits.len() != 32 || !digits.is_ascii() {
+                        return Err(invalid());
+                    }
+                    for (x, vx) in parsed.v.iter_mut().enumerate() {
+                        *vx = Some(parse_byte(&digits[2 * x..2 * x + 2]).ok_or_else(invalid)?);
+                    }
+                }
+                _ if key.len() == 2 && key.starts_with('v') => {
+                    if let Ok(x) = usize::from_str_radix(&key[1..], 16) {
+                        parsed.v[x] = Some(parse_byte(value).ok_or_else(invalid)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(parsed))
+    }
+
+    /// Names the fields that both lines carry but disagree on.
+    pub fn differing_fields(&self, other: &TraceLine) -> Vec<String> {
+        fn differs<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+
+        let mut fields = Vec::new();
+        if differs(self.pc, other.pc) {
+            fields.push("pc".to_string());
+        }
+        if differs(self.opcode, other.opcode) {
+            fields.push("op".to_string());
+        }
+        if differs(self.ri, other.ri) {
+            fields.push("I".to_string());
+        }
+        if differs(self.dt, other.dt) {
+            fields.push("DT".to_string());
+        }
+        if differs(self.st, other.st) {
+            fields.push("ST".to_string());
+        }
+        for (x, (a, b)) in self.v.iter().zip(other.v.iter()).enumerate() {
+            if differs(*a, *b) {
+                fields.push(format!("V{:X}", x));
+            }
+        }
+        fields
+    }
+}
+
+pub fn parse_trace<R: BufRead>(reader: R) -> Result<Vec<TraceLine>, TraceParseError> {
+    let mut lines = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(TraceParseError::Io)?;
+        let parsed = TraceLine::parse(&line, lines.len() as u64).map_err(|field| {
+            TraceParseError::InvalidField {
+                line: number + 1,
+                field,
+            }
+        })?;
+        lines.extend(parsed);
+    }
+    Ok(lines)
+}
+
+/// Aligns both traces by step and returns the first step where they differ,
+/// along with up to `context` of our preceding lines and of each trace's
+/// following lines. Steps missing from
+/// either trace are skipped, but a trace that ends while the other goes on
+/// diverges at the first step it lacks.
+pub fn first_divergence<'a>(
+    ours: &'a [TraceLine],
+    theirs: &'a [TraceLine],
+    context: usize,
+) -> Option<Divergence<'a>> {
+    let (mut a, mut b) = (0, 0);
+    while a < ours.len() && b < theirs.len() {
+        let (left, right) = (&ours[a], &theirs[b]);
+        if left.step < right.step {
+            a += 1;
+        } else if left.step > right.step {
+            b += 1;
+        } else {
+            let fields = left.differing_fields(right);
+            if !fields.is_empty() {
+                return Some(Divergence {
+                    step: left.step,
+                    ours: Some(left),
+                    theirs: Some(right),
+                    fields,
+                    context: &ours[a.saturating_sub(context)..a],
+                    ours_after: following(ours, a, context),
+                    theirs_after: following(theirs, b, context),
+                });
+            }
+            a += 1;
+            b += 1;
+        }
+    }
+    let (left, right) = (ours.get(a), theirs.get(b));
+    let step = left.or(right)?.step;
+    Some(Divergence {
+        step,
+        ours: left,
+        theirs: right,
+        fields: Vec::new(),
+        context: &ours[a.saturating_sub(context)..a],
+        ours_after: following(ours, a, context),
+        theirs_after: following(theirs, b, context),
+    })
+}
+
+fn following(lines: &[TraceLine], at: usize, count: usize) -> &[TraceLine] {
+    let start = (at + 1).min(lines.len());
+    &lines[start..(start + count).min(lines.len())]
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn parse_byte(value: &str) -> Option<u8> {
+    parse_hex(value).filter(|&n| n <= 0xFF).map(|n| n as u8)
+}
+
+impl fmt::Display for TraceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::InvalidField { line, field } => {
+                write!(f, "line {}: invalid field `{}`", line, field)
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Display for Divergence<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traces diverge at step {}", self.step)?;
+        for line in self.context.iter() {
+            writeln!(f, "          {}", line.text)?;
+        }
+        let text = |line: Option<&'a TraceLine>| line.map_or("(trace ended)", |line| &line.text);
+        writeln!(f, "  ours:   {}", text(self.ours))?;
+        write!(f, "  theirs: {}", text(self.theirs))?;
+        if !self.fields.is_empty() {
+            write!(f, "\n  differs: {}", self.fields.join(" "))?;
+        }
+        for (label, lines) in [("ours", self.ours_after), ("theirs", self.theirs_after)] {
+            if !lines.is_empty() {
+                write!(f, "\n  {} after:", label)?;
+            }
+            for line in lines.iter() {
+                write!(f, "\n          {}", line.text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const OURS: &str = "\
+step=0 pc=0200 op=6005 i=0000 dt=00 st=00 v=05000000000000000000000000000000 | V0=05 | LD V0, 0x05
+step=1 pc=0202 op=8006 i=0000 dt=00 st=00 v=02000000000000000000000000000001 | V0=02 VF=01 | SHR V0, V0
+step=2 pc=0204 op=1204 i=0000 dt=00 st=00 v=02000000000000000000000000000001 | - | JP 0x204
+";
+
+    #[test]
+    fn parse_own_format() {
+        let lines = parse_trace(OURS.as_bytes()).unwrap();
+        assert_eq!(3, lines.len());
+        assert_eq!(Some(0x202), lines[1].pc);
+        assert_eq!(Some(0x8006), lines[1].opcode);
+        assert_eq!(Some(0x02), lines[1].v[0x0]);
+        assert_eq!(Some(0x01), lines[1].v[0xF]);
+        assert_eq!(Some(0), lines[1].v[0x1]);
+    }
+
+    #[test]
+    fn parse_reference_format() {
+        let trace = "# emulator output\nPC=0x200 V0=5\n\nPC=0x202 V0=0x2 VF=0 extra=1\n";
+        let lines = parse_trace(trace.as_bytes()).unwrap();
+        assert_eq!(2, lines.len());
+        assert_eq!(1, lines[1].step);
+        assert_eq!(Some(0x202), lines[1].pc);
+        assert_eq!(Some(0), lines[1].v[0xF]);
+        assert_eq!(None, lines[1].v[0x1]);
+        assert_eq!(None, lines[1].ri);
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        match parse_trace("pc=zz".as_bytes()) {
+            Err(TraceParseError::InvalidField { line, field }) => {
+                assert_eq!(1, line);
+                assert_eq!("pc=zz", field);
+            }
+            _ => panic!("expected an invalid field"),
+        }
+    }
+
+    #[test]
+    fn identical_traces_do_not_diverge() {
+        let lines = parse_trace(OURS.as_bytes()).unwrap();
+        assert!(first_divergence(&lines, &lines, 2).is_none());
+    }
+
+    #[test]
+    fn divergence_reports_differing_registers() {
+        let ours = parse_trace(OURS.as_bytes()).unwrap();
+        let theirs =
+            parse_trace("pc=200 v0=05\npc=202 v0=02 vf=00\npc=204 v0=02".as_bytes()).unwrap();
+        let divergence = first_divergence(&ours, &theirs, 2).unwrap();
+        assert_eq!(1, divergence.step);
+        assert_eq!(vec!["VF".to_string()], divergence.fields);
+        assert_eq!(1, divergence.context.len());
+    }
+
+    #[test]
+    fn divergence_includes_following_lines() {
+        let ours = parse_trace(OURS.as_bytes()).unwrap();
+        let theirs = parse_trace("pc=200\npc=204\npc=206\npc=208\npc=20a".as_bytes()).unwrap();
+        let divergence = first_divergence(&ours, &theirs, 2).unwrap();
+        assert_eq!(1, divergence.step);
+        assert_eq!(&ours[2..], divergence.ours_after);
+        assert_eq!(&theirs[2..4], divergence.theirs_after);
+        assert!(divergence
+            .to_string()
+            .ends_with("  theirs after:\n          pc=206\n          pc=208"));
+
+        let divergence = first_divergence(&ours, &theirs, 0).unwrap();
+        assert!(divergence.ours_after.is_empty());
+        assert!(divergence.theirs_after.is_empty());
+    }
+
+    #[test]
+    fn divergence_aligns_by_step() {
+        let ours = parse_trace(OURS.as_bytes()).unwrap();
+        let theirs = parse_trace("step=2 pc=206".as_bytes()).unwrap();
+        let divergence = first_divergence(&ours, &theirs, 0).unwrap();
+        assert_eq!(2, divergence.step);
+        assert_eq!(vec!["pc".to_string()], divergence.fields);
+    }
+
+    #[test]
+    fn truncated_trace_diverges() {
+        let ours = parse_trace(OURS.as_bytes()).unwrap();
+        let theirs = parse_trace(
+            "pc=200 v0=05
+pc=202 v0=02"
+                .as_bytes(),
+        )
+        .unwrap();
+        let divergence = first_divergence(&ours, &theirs, 1).unwrap();
+        assert_eq!(2, divergence.step);
+        assert_eq!(Some(&ours[2]), divergence.ours);
+        assert_eq!(None, divergence.theirs);
+        assert!(divergence.fields.is_empty());
+        assert!(divergence.to_string().ends_with("theirs: (trace ended)"));
+
+        let divergence = first_divergence(&ours[..1], &ours, 0).unwrap();
+        assert_eq!(1, divergence.step);
+        assert_eq!(None, divergence.ours);
+    }
+}
@@ -0,0 +1,35 @@
+/// The CHIP-8 platforms chipper knows about, named after the platforms in
+/// the community chip-8-database. Only the base instruction set runs, but
+/// the variant still decides platform details such as the stack depth.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    /// The original interpreter on the COSMAC VIP.
+    OriginalChip8,
+    /// CHIP-8 as most modern interpreters and ROMs expect it.
+    #[default]
+    ModernChip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Variant {
+    /// The variant for a platform id from the chip-8-database. Platforms
+    /// with their own hardware, like the CHIP-8X and MEGA-CHIP, have none.
+    pub fn from_platform(id: &str) -> Option<Self> {
+        match id {
+            "originalChip8" | "hybridVIP" => Some(Variant::OriginalChip8),
+            "modernChip8" => Some(Variant::ModernChip8),
+            "chip48" | "superchip1" | "superchip" => Some(Variant::SuperChip),
+            "xochip" => Some(Variant::XoChip),
+            _ => None,
+        }
+    }
+
+    /// How many nested subroutine calls the platform supports.
+    pub fn stack_depth(self) -> usize {
+        match self {
+            Variant::OriginalChip8 => 12,
+            Variant::ModernChip8 | Variant::SuperChip | Variant::XoChip => 16,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Runs ROMs headlessly and checks their final screens against fixtures in
+//! `tests/fixtures`.
+//!
+//! Community test suites are not shipped with the crate. To check one, run it
+//! with `chipper-test --write` once, review the image, and add a test here
+//! that loads the ROM and compares against the saved image.
+
+use chipper::harness::Harness;
+use chipper::keypad::ChipKey;
+use chipper::screenshot;
+use chipper::timing::Timing;
+
+// Draws "C8", then waits for a key and draws its digit after them.
+const SHOW_KEY: &[u8] = &[
+    0x60, 0x0C, // LD V0, 0x0C
+    0x61, 0x04, // LD V1, 0x04
+    0x62, 0x04, // LD V2, 0x04
+    0xF0, 0x29, // LD F, V0
+    0xD1, 0x25, // DRW V1, V2, 5
+    0x60, 0x08, // LD V0, 0x08
+    0x71, 0x05, // ADD V1, 0x05
+    0xF0, 0x29, // LD F, V0
+    0xD1, 0x25, // DRW V1, V2, 5
+    0xF0, 0x0A, // LD V0, K
+    0x71, 0x05, // ADD V1, 0x05
+    0xF0, 0x29, // LD F, V0
+    0xD1, 0x25, // DRW V1, V2, 5
+    0x12, 0x1A, // JP 0x21A
+];
+
+fn fixture(name: &str) -> chipper::graphics::Graphics {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    screenshot::load(path).unwrap()
+}
+
+#[test]
+fn show_key_matches_fixture() {
+    let run = Harness::new(10)
+        .press(5, ChipKey::CK_A)
+        .run(SHOW_KEY)
+        .unwrap();
+    assert_eq!(None, run.fault);
+    if let Err(mismatch) = run.compare(&fixture("show_key.pbm")) {
+        panic!("{}", mismatch);
+    }
+}
+
+#[test]
+fn show_key_matches_png_fixture() {
+    let run = Harness::new(10)
+        .press(5, ChipKey::CK_A)
+        .run(SHOW_KEY)
+        .unwrap();
+    assert!(run.compare(&fixture("show_key.png")).is_ok());
+}
+
+#[test]
+fn show_key_matches_under_vip_timing() {
+    let run = Harness::new(10)
+        .timing(Timing::Vip)
+        .press(5, ChipKey::CK_A)
+        .run(SHOW_KEY)
+        .unwrap();
+    assert!(run.compare(&fixture("show_key.pbm")).is_ok());
+}